@@ -0,0 +1,205 @@
+//! Conversion of volumes to and from `ndarray` arrays
+//!
+//! Volumes are represented as `Array3` shaped (`nz`, `ny`, `nx`) in standard (row major) layout,
+//! so that the columns are the fastest changing axis as in the data block. Complex voxels are
+//! `[T; 2]` of the real and imaginary part and RGB voxels are `[u8; 3]`.
+//!
+//! Requires the `ndarray` feature.
+
+use crate::decoder::header::Header;
+use crate::decoder::{Decoder, DecodingResult};
+use crate::encoder::{Encoder, EncodingBuffer};
+use crate::{Mode, MrcResult, MrcUnsupportedError};
+use ndarray::{Array3, ArrayView3};
+use std::io::{Read, Seek, Write};
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// Types of voxels an array of a volume can hold
+pub trait Element: Clone + private::Sealed {
+    /// Takes the voxels out of `result`, `None` if they are of another type
+    #[doc(hidden)]
+    fn from_result(result: DecodingResult) -> Option<Vec<Self>>;
+
+    /// The voxels as data for the encoder
+    #[doc(hidden)]
+    fn buffer(data: &[Self]) -> EncodingBuffer<'_>;
+}
+
+macro_rules! scalar_element {
+    ($ty:ty, $variant:ident) => {
+        impl private::Sealed for $ty {}
+
+        impl Element for $ty {
+            fn from_result(result: DecodingResult) -> Option<Vec<$ty>> {
+                match result {
+                    DecodingResult::$variant(data) => Some(data),
+                    _ => None,
+                }
+            }
+
+            fn buffer(data: &[$ty]) -> EncodingBuffer<'_> {
+                EncodingBuffer::$variant(data)
+            }
+        }
+    };
+}
+
+macro_rules! compound_element {
+    ($ty:ty, $n:expr, $variant:ident) => {
+        impl private::Sealed for [$ty; $n] {}
+
+        impl Element for [$ty; $n] {
+            fn from_result(result: DecodingResult) -> Option<Vec<[$ty; $n]>> {
+                match result {
+                    DecodingResult::$variant(data) => Some(
+                        data.chunks_exact($n)
+                            .map(|voxel| {
+                                let mut samples = [<$ty>::default(); $n];
+                                samples.copy_from_slice(voxel);
+                                samples
+                            })
+                            .collect(),
+                    ),
+                    _ => None,
+                }
+            }
+
+            fn buffer(data: &[[$ty; $n]]) -> EncodingBuffer<'_> {
+                EncodingBuffer::$variant(data.as_flattened())
+            }
+        }
+    };
+}
+
+scalar_element!(i8, I8);
+scalar_element!(u8, U8);
+scalar_element!(i16, I16);
+scalar_element!(u16, U16);
+scalar_element!(f32, F32);
+compound_element!(i16, 2, ComplexI16);
+compound_element!(f32, 2, ComplexF32);
+compound_element!(u8, 3, U8);
+
+impl<R: Read + Seek> Decoder<R> {
+    /// Decodes the volume into an array shaped (`nz`, `ny`, `nx`)
+    ///
+    /// `T` has to be the type of the voxels `read_volume` decodes, e.g. `f32` for `Mode2` and
+    /// `Mode12` or `[i16; 2]` for `Mode3`.
+    pub fn read_array<T: Element>(&mut self) -> MrcResult<Array3<T>> {
+        let [nx, ny, nz] = self.header()?.dimensions();
+        let data =
+            T::from_result(self.read_volume()?).ok_or(MrcUnsupportedError::UnsupportedDataType)?;
+        // A count of voxels other than the volume has means that `T` has a different number of
+        // samples, e.g. `u8` for RGB voxels
+        Array3::from_shape_vec((nz, ny, nx), data)
+            .map_err(|_| MrcUnsupportedError::UnsupportedDataType.into())
+    }
+}
+
+impl<W: Write + Seek> Encoder<W> {
+    /// Writes an array shaped (`nz`, `ny`, `nx`) as a volume in `mode`
+    ///
+    /// Arrays that are not in standard layout are copied first.
+    pub fn write_array<T: Element>(&mut self, mode: Mode, array: ArrayView3<T>) -> MrcResult<()> {
+        let (nz, ny, nx) = array.dim();
+        let array = array.as_standard_layout();
+        let data = array
+            .as_slice()
+            .expect("array in standard layout is contiguous");
+        self.write_volume(mode, [nx, ny, nz], T::buffer(data))
+    }
+}
+
+/// Reorders an array read in file order (sections, rows, columns) to (Z, Y, X)
+///
+/// The axes of the columns, rows and sections are given by `mapc`, `mapr` and `maps` of the
+/// `header`, which have to be a permutation of 1, 2 and 3.
+pub fn canonical_axes<T: Clone>(array: Array3<T>, header: &Header) -> MrcResult<Array3<T>> {
+    let [column, row, section] = header.axis_permutation()?;
+    // Array axes are reversed, i.e. sections, rows, columns and Z, Y, X
+    let file_axes = [2 - section, 2 - row, 2 - column];
+    let mut axes = [0; 3];
+    for (i, &axis) in file_axes.iter().enumerate() {
+        axes[axis] = i;
+    }
+
+    let array = array.permuted_axes(axes);
+    if array.is_standard_layout() {
+        Ok(array)
+    } else {
+        Ok(array.as_standard_layout().into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::ByteOrder;
+    use ndarray::{Array, ShapeBuilder};
+    use std::io::Cursor;
+
+    fn encode<T: Element>(mode: Mode, array: ArrayView3<T>) -> Vec<u8> {
+        let mut encoder =
+            Encoder::new(Cursor::new(Vec::new())).with_byte_order(ByteOrder::LittleEndian);
+        encoder.write_array(mode, array).unwrap();
+        encoder.into_inner().into_inner()
+    }
+
+    #[test]
+    fn arrays_are_shaped_sections_rows_columns() {
+        let array = Array::from_shape_fn((2, 3, 4), |(z, y, x)| (x + 4 * y + 12 * z) as f32);
+        let mut decoder = Decoder::new(Cursor::new(encode(Mode::Mode2, array.view()))).unwrap();
+        assert_eq!(decoder.header().unwrap().dimensions(), [4, 3, 2]);
+        let decoded = decoder.read_array::<f32>().unwrap();
+        assert_eq!(decoded, array);
+
+        // Columns stay the fastest changing axis whatever the memory layout
+        let fortran = Array::from_shape_fn((2, 3, 4).f(), |(z, y, x)| (x + 4 * y + 12 * z) as f32);
+        assert_eq!(
+            encode(Mode::Mode2, fortran.view()),
+            encode(Mode::Mode2, array.view())
+        );
+
+        let mut decoder = Decoder::new(Cursor::new(encode(Mode::Mode2, array.view()))).unwrap();
+        assert!(decoder.read_array::<i16>().is_err());
+    }
+
+    #[test]
+    fn arrays_of_compound_voxels() {
+        let complex = Array::from_shape_fn((1, 2, 2), |(_, y, x)| [x as f32, -(y as f32)]);
+        let mut decoder = Decoder::new(Cursor::new(encode(Mode::Mode4, complex.view()))).unwrap();
+        assert_eq!(decoder.read_array::<[f32; 2]>().unwrap(), complex);
+
+        let rgb = Array::from_shape_fn((1, 1, 3), |(_, _, x)| [x as u8, 10, 20]);
+        let mut decoder = Decoder::new(Cursor::new(encode(Mode::Mode16, rgb.view()))).unwrap();
+        assert_eq!(decoder.read_array::<[u8; 3]>().unwrap(), rgb);
+    }
+
+    #[test]
+    fn arrays_of_permuted_axes() {
+        // Columns along Z, rows along X and sections along Y
+        let file = Array::from_shape_fn((4, 3, 2), |(s, r, c)| (c + 2 * r + 6 * s) as i16);
+        let mut bytes = encode(Mode::Mode1, file.view());
+        for (i, &axis) in [3i32, 1, 2].iter().enumerate() {
+            bytes[64 + 4 * i..68 + 4 * i].copy_from_slice(&axis.to_le_bytes());
+        }
+
+        let mut decoder = Decoder::new(Cursor::new(bytes.clone())).unwrap();
+        let array = decoder.read_array::<i16>().unwrap();
+        assert_eq!(array, file);
+        let canonical = canonical_axes(array, decoder.header().unwrap()).unwrap();
+        assert_eq!(canonical.dim(), (2, 4, 3));
+        assert!(canonical.is_standard_layout());
+        for ((z, y, x), &value) in canonical.indexed_iter() {
+            assert_eq!(value, file[[y, x, z]]);
+        }
+
+        let mut decoder = Decoder::new(Cursor::new(bytes))
+            .unwrap()
+            .with_canonical_axes(true);
+        assert_eq!(decoder.read_array::<i16>().unwrap(), canonical);
+    }
+}
@@ -7,27 +7,62 @@
 //! SAFETY: These are benign casts as we apply them to fixed size integer types only. All of them
 //! are naturally aligned, valid for all bit patterns and their alignment is surely at most their
 //! size (we assert the latter fact since it is 'implementation defined' if following the letter of
-//! the unsafe code guidelines).
+//! the unsafe code guidelines). Casting bytes back to these types only succeeds for bytes that are
+//! suitably aligned, which is checked at runtime since mapped files give no such guarantee.
 //!
 //! TODO: Would like to use std-lib here.
 use std::{mem, slice};
 
-macro_rules! integral_slice_as_bytes{($int:ty, $const:ident $(,$mut:ident)*) => {
-    pub(crate) fn $const(slice: &[$int]) -> &[u8] {
+// Each cast is generated only for the element types whose decoding (`mut`) or encoding paths
+// need it.
+macro_rules! integral_slice_as_bytes{($int:ty $(, const $const:ident)? $(, mut $mut:ident)?) => {
+    $(pub(crate) fn $const(slice: &[$int]) -> &[u8] {
         assert!(mem::align_of::<$int>() <= mem::size_of::<$int>());
         unsafe { slice::from_raw_parts(slice.as_ptr() as *const u8, mem::size_of_val(slice)) }
-    }
+    })?
     $(pub(crate) fn $mut(slice: &mut [$int]) -> &mut [u8] {
         assert!(mem::align_of::<$int>() <= mem::size_of::<$int>());
         unsafe { slice::from_raw_parts_mut(slice.as_mut_ptr() as *mut u8, mem::size_of_val(slice)) }
-    })*
+    })?
 }}
 
-integral_slice_as_bytes!(i8, i8_as_ne_bytes);
-integral_slice_as_bytes!(u16, u16_as_ne_bytes, u16_as_ne_mut_bytes);
-integral_slice_as_bytes!(i16, i16_as_ne_bytes);
-integral_slice_as_bytes!(u32, u32_as_ne_bytes, u32_as_ne_mut_bytes);
-integral_slice_as_bytes!(i32, i32_as_ne_bytes);
-integral_slice_as_bytes!(u64, u64_as_ne_bytes, u64_as_ne_mut_bytes);
-integral_slice_as_bytes!(f32, f32_as_ne_bytes, f32_as_ne_mut_bytes);
-integral_slice_as_bytes!(f64, f64_as_ne_bytes, f64_as_ne_mut_bytes);
+integral_slice_as_bytes!(i8, const i8_as_ne_bytes, mut i8_as_ne_mut_bytes);
+integral_slice_as_bytes!(u16, const u16_as_ne_bytes, mut u16_as_ne_mut_bytes);
+integral_slice_as_bytes!(i16, const i16_as_ne_bytes, mut i16_as_ne_mut_bytes);
+integral_slice_as_bytes!(u32, const u32_as_ne_bytes, mut u32_as_ne_mut_bytes);
+integral_slice_as_bytes!(i32, const i32_as_ne_bytes, mut i32_as_ne_mut_bytes);
+integral_slice_as_bytes!(u64, const u64_as_ne_bytes, mut u64_as_ne_mut_bytes);
+integral_slice_as_bytes!(f32, const f32_as_ne_bytes, mut f32_as_ne_mut_bytes);
+integral_slice_as_bytes!(f64, const f64_as_ne_bytes, mut f64_as_ne_mut_bytes);
+
+// The reverse casts, returning `None` when the bytes are misaligned or not a whole number of
+// elements. Only mapped files need them.
+macro_rules! ne_bytes_as_integral_slice {
+    ($int:ty, $const:ident, $mut:ident) => {
+        #[cfg(feature = "mmap")]
+        pub(crate) fn $const(bytes: &[u8]) -> Option<&[$int]> {
+            if !(bytes.as_ptr() as usize).is_multiple_of(mem::align_of::<$int>())
+                || !bytes.len().is_multiple_of(mem::size_of::<$int>())
+            {
+                return None;
+            }
+            let len = bytes.len() / mem::size_of::<$int>();
+            Some(unsafe { slice::from_raw_parts(bytes.as_ptr() as *const $int, len) })
+        }
+        #[cfg(feature = "mmap")]
+        pub(crate) fn $mut(bytes: &mut [u8]) -> Option<&mut [$int]> {
+            if !(bytes.as_ptr() as usize).is_multiple_of(mem::align_of::<$int>())
+                || !bytes.len().is_multiple_of(mem::size_of::<$int>())
+            {
+                return None;
+            }
+            let len = bytes.len() / mem::size_of::<$int>();
+            Some(unsafe { slice::from_raw_parts_mut(bytes.as_mut_ptr() as *mut $int, len) })
+        }
+    };
+}
+
+ne_bytes_as_integral_slice!(i8, ne_bytes_as_i8, ne_mut_bytes_as_i8);
+ne_bytes_as_integral_slice!(u16, ne_bytes_as_u16, ne_mut_bytes_as_u16);
+ne_bytes_as_integral_slice!(i16, ne_bytes_as_i16, ne_mut_bytes_as_i16);
+ne_bytes_as_integral_slice!(f32, ne_bytes_as_f32, ne_mut_bytes_as_f32);
@@ -1,135 +1,1322 @@
-#[derive(Debug)]
+use super::stream::{EndianReader, SmartReader};
+use crate::encoder::stream::{EndianWriter, SmartWriter};
+use crate::error::MrcFormatError;
+use crate::{Mode, MrcResult};
+use std::convert::TryFrom;
+use std::io::{Read, Seek, Write};
+
+/// Size of the main header in bytes
+pub const HEADER_SIZE: u64 = 1024;
+
+/// Offset of the machine stamp (`MACHST`) in bytes
+pub(crate) const MACHINE_STAMP_OFFSET: u64 = 212;
+
+/// Offset of the minimum density (`amin`) in bytes, followed by `amax` and `amean`
+#[cfg(feature = "mmap")]
+pub(crate) const DENSITY_OFFSET: u64 = 76;
+
+/// Offset of the rms deviation from the mean density (`rms`) in bytes
+#[cfg(feature = "mmap")]
+pub(crate) const RMS_OFFSET: u64 = 216;
+
+/// Value of `imodStamp` in files written by IMOD
+pub const IMOD_STAMP: i32 = 1_146_047_817;
+
+/// Value of `nDVID` in files written by Priism (IVE) and DeltaVision
+pub const IVE_ID: i16 = -16224;
+
+/// Offset of `nDVID` in bytes
+pub(crate) const IVE_ID_OFFSET: u64 = 96;
+
+/// Number of the text labels in the header
+pub(crate) const NUM_LABELS: usize = 10;
+
+/// Length of a single text label in bytes
+pub(crate) const LABEL_SIZE: usize = 80;
+
+#[derive(Debug, Clone)]
 // TODO: make the structure of the header more acceptable by grouping, like [nx,ny,nz] as dimensions and so on <01-10-20, kunzaatko> //
 pub struct Header {
     /// Number of columns in 3D data array
     ///
     /// NOTE: fast axis
     /// NOTE: The data block of an MRC format file holds a 3D array of data (of type specified by `mode`). `nx`, `ny`, `nz` specify the dimensions (in grid points) of this array. In EM, this will correspond to the dimensions of a volume/map, or the combined size of an image/volume stack. In crystallography, this will correspond to the dimensions of a map, which may cover a crystallographic unit cell or may cover some fraction or multiple of a unit cell.
-    nx: i32, // 1-4
+    pub(crate) nx: i32, // 1-4
 
     /// Number of rows in 3D data array
     /// NOTE: medium axis
-    ny: i32, // 5-8
+    pub(crate) ny: i32, // 5-8
 
     /// Number of sections in 3D data array
     /// NOTE: slow axis
-    nz: i32, // 9-12
+    pub(crate) nz: i32, // 9-12
 
     /// Data type
     /// TODO(DOCS): input table of possible modes <01-10-20, kunzaatko>
     /// NOTE: In the MRC2014 format, `mode=0` has been clarified as signed, and `mode=6` has been added for 16-bit unsigned integer data.
-    mode: Option<i32>, // 13-16
+    pub(crate) mode: i32, // 13-16
 
     /// Number of first column in map (Default = 0)
-    nxstart: i32, // 17-20
+    pub(crate) nxstart: i32, // 17-20
 
     /// Number of first row in map (Default = 0)
-    nystart: i32, // 21-24
+    pub(crate) nystart: i32, // 21-24
 
     /// Number of first section in map (Default = 0)
-    nzstart: i32, // 25-28
+    pub(crate) nzstart: i32, // 25-28
 
     /// Number of intervals along X of the "unit cell"
-    mx: Option<i32>, // 29-32
+    pub(crate) mx: i32, // 29-32
 
     /// Number of intervals along Y of the "unit cell"
-    my: Option<i32>, // 33-36
+    pub(crate) my: i32, // 33-36
 
     /// Number of intervals along Z of the "unit cell"
     /// NOTE: In crystallographic usage, `mz` represents the number of intervals, or sampling grid, along Z in a crystallographic unit cell. This need not be the same as `nz`, if the map doesn't cover exactly a single unit cell. For microscopy, where there is no unit cell, `mz` represents the number of sections in a single volume. For a volume stack, `nz`/`mz` will be the number of volumes in the stack. For images, `mz` = 1.
-    mz: Option<i32>, // 37-40
+    pub(crate) mz: i32, // 37-40
 
     /// Cell X length in angstroms
-    xlen: Option<f32>, // 41-44
+    pub(crate) xlen: f32, // 41-44
 
     /// Cell Y length in angstroms
-    ylen: Option<f32>, // 45-48
+    pub(crate) ylen: f32, // 45-48
 
     /// Cell Z length in angstroms
-    zlen: Option<f32>, // 41-52
+    pub(crate) zlen: f32, // 41-52
 
     /// Cell angles in degrees // TODO(DOCS): specify the concrete angles that are defined by these values <01-10-20, kunzaatko> //
-    alpha: Option<f32>, // 53-56
-    beta: Option<f32>, // 57-60
-    gama: Option<f32>, // 61-64
+    pub(crate) alpha: f32, // 53-56
+    pub(crate) beta: f32, // 57-60
+    pub(crate) gama: f32, // 61-64
 
     /// Axis corresponding to columns (1=X, 2=Y, 3=Z)
     /// NOTE: In EM `mapc`,`mapr`,`maps` = 1,2,3 so that sections and images are perpendicular to the Z axis. In crystallography, other orderings are possible. For example, in some spacegroups it is convenient to section along the Y axis (i.e. where this is the polar axis).
-    mapc: Option<i32>, // 65-68
+    pub(crate) mapc: i32, // 65-68
 
     /// Axis corresponding to rows (1=X, 2=Y, 3=Z)
-    mapr: Option<i32>, // 69-72
+    pub(crate) mapr: i32, // 69-72
 
     /// Axis corresponding to sections (1=X, 2=Y, 3=Z)
-    maps: Option<i32>, // 73-76
+    pub(crate) maps: i32, // 73-76
 
     /// Minimum pixel/density value
     /// NOTE: Density statistics may not be kept up-to-date for image/volume stacks, since it is expensive to recalculate these every time a new image/volume is added/deleted. We have proposed the following convention: `amax` < `amin`, `amean` < min({`amin`, `amax`}), `rms` < 0 each indicate that the quantity in question is not well determined.
-    amin: Option<f32>, // 77-80
+    pub(crate) amin: f32, // 77-80
 
     /// Maximum pixel/density value
-    amax: Option<f32>, // 81-84
+    pub(crate) amax: f32, // 81-84
 
     /// Mean pixel/density value
-    amean: Option<f32>, // 85-88
+    pub(crate) amean: f32, // 85-88
 
     /// Space group number 0 or 1
     /// NOTE: Spacegroup 0 implies a 2D image or image stack. For crystallography, ISPG represents the actual spacegroup. For single volumes from EM/ET, the spacegroup should be 1. For volume stacks, we adopt the convention that `ispg` is the spacegroup number + 400, which in EM/ET will typically be 401.
-    ispg: Option<i32>, // 89-92
+    pub(crate) ispg: i32, // 89-92
 
     /// Number of bytes used for symmetry data (0 or 80)
     ///
     /// NOTE: `nsymbt` specifies the size of the extended header in bytes, whether it contains symmetry records (as in the original format definition) or any other kind of additional metadata.
-    nsymbt: Option<i32>, // 93-96
+    pub(crate) nsymbt: i32, // 93-96
 
     /// Extra space used for anything
-    extra: Option<Extra>, // 97-196
+    pub(crate) extra: Extra, // 97-196
 
     /// Origin in X,Y,Z used for transforms
     /// NOTE: For transforms (`mode` 3 or 4), `origin` is the phase origin of the transformed image in pixels, e.g. as used in helical processing of the MRC package. For a transform of a padded image, this value corresponds to the pixel position in the padded image of the center of the unpadded image.
     /// NOTE: For other modes, `origin` specifies the real space location of a subvolume taken from a larger volume. In the (2-dimensional) example shown above, the header of the map containing the subvolume (red rectangle) would contain `origin` = 100, 120 to specify its position with respect to the original volume (assuming the original volume has its own `origin` set to 0, 0).
-    origin: Option<Origin>, // 197-208
+    /// NOTE: Priism files hold the wavelengths here, their origin is read from bytes 209-220.
+    pub(crate) origin: Origin, // 197-208
 
     /// Character string 'MAP ' to identify file type
-    map: String, // 209-212
+    pub(crate) map: String, // 209-212
 
     /// Machine stamp
     /// NOTE: Bytes 213 and 214 contain 4 `nibbles' (half-bytes) indicating the representation of float, complex, integer and character datatypes. Bytes 215 and 216 are unused. The CCP4 library contains a general representation of datatypes, but in practice it is safe to use 0x44 0x44 0x00 0x00 for little endian machines, and 0x11 0x11 0x00 0x00 for big endian machines. The CCP4 library uses this information to automatically byte-swap data if appropriate, when tranferring data files between machines.
-    mach_st: String, // 213-216
+    pub(crate) mach_st: [u8; 4], // 213-216
 
     /// rms deviation of map from mean density
-    rms: Option<f32>, // 217-220
-
-    /// Number of labels being used (lables are 10, 80 character(ASCII) texts included after the header in memory)
-    nlabl: i32, //? 221-224
+    pub(crate) rms: f32, // 217-220
 
     /// 10 × 80 character text labels
-    label: Option<Vec<String>>,
+    ///
+    /// NOTE: Only the first `nlabl` (221-224) labels, the ones being used, are kept.
+    pub(crate) label: Vec<String>, // 225-1024
 }
 
-#[derive(Debug)]
-struct Extra {
+#[derive(Debug, Clone)]
+pub(crate) struct Extra {
+    /// Identifier of files written by Priism (IVE), see `IVE_ID`
+    /// NOTE: Priism lays out the rest of the header differently, see `crate::extended::ive`.
+    pub(crate) ive_id: i16, // 97-98
+
     /// Code for the type of extended header
     ///
     /// NOTE: A code for the kind of metadata held in the extended header. Currently agreed values are:
-    /// __CCP4__	Format from CCP4 suite
-    /// __MRCO__	MRC format
-    /// __SERI__	SerialEM. Details in the IMOD documentation.
-    /// __AGAR__	Agard
-    /// __FEI1__	FEI software, e.g. EPU and Xplore3D, Amira, Avizo. Documented in the EPU User Manual, Appendix C.
-    /// __HDF5__	Metadata in HDF5 format
-    ext_type: String, // 105-108
+    /// - __CCP4__ Format from CCP4 suite
+    /// - __MRCO__ MRC format
+    /// - __SERI__ SerialEM. Details in the IMOD documentation.
+    /// - __AGAR__ Agard
+    /// - __FEI1__ FEI software, e.g. EPU and Xplore3D, Amira, Avizo. Documented in the EPU User Manual, Appendix C.
+    /// - __HDF5__ Metadata in HDF5 format
+    pub(crate) ext_type: String, // 105-108
 
     /// Version of the MRC format
     /// NOTE: The version of the MRC format that the file adheres to, specified as a 32-bit integer and calculated as:
     /// - Year * 10 + version within the year (base 0)
+    ///
     /// NOTE: For the current format change, the value would be 20140.
-    nversion: i32,
+    pub(crate) nversion: i32, // 109-112
+
+    /// Number of integers (or bytes) per section in the extended header
+    /// NOTE: SerialEM stores the number of bytes of each per-section record, Agard the number of
+    /// integers. Not part of MRC2014.
+    pub(crate) nint: i16, // 129-130
+
+    /// Number of reals (or flags) per section in the extended header
+    /// NOTE: SerialEM stores bit flags of the fields the per-section records hold, Agard the number
+    /// of reals. Not part of MRC2014.
+    pub(crate) nreal: i16, // 131-132
+
+    /// Stamp identifying files written by IMOD, see `IMOD_STAMP`
+    pub(crate) imod_stamp: i32, // 153-156
+
+    /// Bit flags of IMOD, only meaningful with `imod_stamp`
+    pub(crate) imod_flags: i32, // 157-160
 }
 
-#[derive(Debug)]
-struct Origin {
-    xorg: f32,
-    yorg: f32,
-    zorg: f32,
+/// Bit flags IMOD stores in the header (`imodFlags`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImodFlags(pub i32);
+
+impl ImodFlags {
+    /// Bit 0: bytes of `Mode0` data are signed. Older IMOD files without it hold unsigned bytes.
+    pub fn signed_bytes(self) -> bool {
+        self.0 & 1 != 0
+    }
+
+    /// Bit 1: pixel spacing was set from the size in the extended header
+    pub fn pixel_spacing_from_extended_header(self) -> bool {
+        self.0 & 2 != 0
+    }
+
+    /// Bit 2: origin is stored with the sign inverted from the MRC2014 definition
+    pub fn inverted_origin(self) -> bool {
+        self.0 & 4 != 0
+    }
+
+    /// Bit 3: rms value is negative if it was not computed
+    pub fn negative_rms_not_computed(self) -> bool {
+        self.0 & 8 != 0
+    }
+
+    /// Bit 4: `Mode0` data hold two 4-bit values per byte, laid out as in `Mode101`
+    pub fn packed_nibbles(self) -> bool {
+        self.0 & 16 != 0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Origin {
+    pub(crate) xorg: f32,
+    pub(crate) yorg: f32,
+    pub(crate) zorg: f32,
+}
+
+/// Generation of the MRC format a file adheres to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FormatVersion {
+    /// MRC2014, `nversion` is 20140 or later
+    Mrc2014,
+    /// Written by IMOD before it adopted MRC2014, recognized by `imodStamp`
+    Imod,
+    /// MRC2000 and CCP4 maps, which carry the `MAP ` tag but no `nversion`
+    Mrc2000,
+    /// Older files without the `MAP ` tag
+    Legacy,
+}
+
+/// Density statistics of the data block (`amin`, `amax`, `amean`, `rms`)
+///
+/// Statistics of image/volume stacks are often not kept up-to-date. MRC2014 proposes the convention
+/// that `amax` < `amin`, `amean` < min(`amin`, `amax`) and `rms` < 0 each indicate that the
+/// quantity in question is not well determined. The raw values are kept in the fields and the
+/// methods apply the convention.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DensityStatistics {
+    /// Minimum pixel/density value
+    pub min: f32,
+    /// Maximum pixel/density value
+    pub max: f32,
+    /// Mean pixel/density value
+    pub mean: f32,
+    /// rms deviation of map from mean density
+    pub rms: f32,
+}
+
+impl DensityStatistics {
+    /// Statistics marked as not determined, following the MRC2014 convention
+    pub fn undetermined() -> DensityStatistics {
+        DensityStatistics {
+            min: 0.0,
+            max: -1.0,
+            mean: -2.0,
+            rms: -1.0,
+        }
+    }
+
+    /// Minimum and maximum value, if determined
+    pub fn range(&self) -> Option<(f32, f32)> {
+        if self.max < self.min {
+            None
+        } else {
+            Some((self.min, self.max))
+        }
+    }
+
+    /// Mean value, if determined
+    pub fn mean(&self) -> Option<f32> {
+        if self.mean < self.min.min(self.max) {
+            None
+        } else {
+            Some(self.mean)
+        }
+    }
+
+    /// rms deviation from the mean, if determined
+    pub fn rms(&self) -> Option<f32> {
+        if self.rms < 0.0 {
+            None
+        } else {
+            Some(self.rms)
+        }
+    }
+}
+
+/// Kind of metadata held in the extended header (`EXTTYP`)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ExtendedHeaderType {
+    /// Format from CCP4 suite
+    CCP4,
+    /// MRC format
+    MRCO,
+    /// SerialEM. Details in the IMOD documentation.
+    SERI,
+    /// Agard, the layout of Priism (IVE), see `crate::extended::ive`
+    AGAR,
+    /// FEI software, e.g. EPU and Xplore3D, Amira, Avizo. Documented in the EPU User Manual, Appendix C.
+    FEI1,
+    /// FEI software, second revision of `FEI1` with additional fields
+    FEI2,
+    /// Metadata in HDF5 format
+    HDF5,
+    /// Any other code
+    Other(String),
+}
+
+impl ExtendedHeaderType {
+    /// The four character code stored in `EXTTYP`
+    pub fn code(&self) -> &str {
+        match self {
+            ExtendedHeaderType::CCP4 => "CCP4",
+            ExtendedHeaderType::MRCO => "MRCO",
+            ExtendedHeaderType::SERI => "SERI",
+            ExtendedHeaderType::AGAR => "AGAR",
+            ExtendedHeaderType::FEI1 => "FEI1",
+            ExtendedHeaderType::FEI2 => "FEI2",
+            ExtendedHeaderType::HDF5 => "HDF5",
+            ExtendedHeaderType::Other(code) => code,
+        }
+    }
+
+    fn from_code(code: &str) -> Option<ExtendedHeaderType> {
+        Some(match code.trim() {
+            "" => return None,
+            "CCP4" => ExtendedHeaderType::CCP4,
+            "MRCO" => ExtendedHeaderType::MRCO,
+            "SERI" => ExtendedHeaderType::SERI,
+            "AGAR" => ExtendedHeaderType::AGAR,
+            "FEI1" => ExtendedHeaderType::FEI1,
+            "FEI2" => ExtendedHeaderType::FEI2,
+            "HDF5" => ExtendedHeaderType::HDF5,
+            other => ExtendedHeaderType::Other(other.to_string()),
+        })
+    }
+}
+
+impl Header {
+    /// Dimensions of the 3D data array (`nx`, `ny`, `nz`)
+    ///
+    /// The order is from the fastest to the slowest changing axis, i.e. columns, rows, sections.
+    pub fn dimensions(&self) -> [usize; 3] {
+        // The dimensions are checked to be non-negative when the header is read
+        [self.nx as usize, self.ny as usize, self.nz as usize]
+    }
+
+    /// Data type of the data block
+    pub fn mode(&self) -> MrcResult<Mode> {
+        Mode::try_from(self.mode)
+    }
+
+    /// Software specific variant of the format the file was written in, if recognized
+    pub fn software(&self) -> Option<Mode> {
+        if self.extra.imod_stamp == IMOD_STAMP {
+            Some(Mode::IMOD)
+        } else if self.extra.ive_id == IVE_ID {
+            Some(Mode::IVE)
+        } else {
+            None
+        }
+    }
+
+    /// IMOD flags (`imodFlags`), `None` if the file was not written by IMOD
+    pub fn imod_flags(&self) -> Option<ImodFlags> {
+        match self.software() {
+            Some(Mode::IMOD) => Some(ImodFlags(self.extra.imod_flags)),
+            _ => None,
+        }
+    }
+
+    /// Whether the bytes of `Mode0` data are signed
+    ///
+    /// MRC2014 defines them as signed, but IMOD wrote unsigned bytes unless it sets the signed
+    /// flag. Priism always writes unsigned bytes.
+    pub fn signed_bytes(&self) -> bool {
+        match self.software() {
+            Some(Mode::IVE) => false,
+            _ => self.imod_flags().is_none_or(ImodFlags::signed_bytes),
+        }
+    }
+
+    /// Layout of the data block
+    ///
+    /// This is `mode` except for IMOD files that store packed 4-bit values under `Mode0`, which are
+    /// laid out as `Mode101`.
+    pub fn data_mode(&self) -> MrcResult<Mode> {
+        let mode = self.mode()?;
+        match self.imod_flags() {
+            Some(flags) if mode == Mode::Mode0 && flags.packed_nibbles() => Ok(Mode::Mode101),
+            _ => Ok(mode),
+        }
+    }
+
+    /// Size of the data block in bytes, as laid out by `data_mode`
+    pub fn data_size(&self) -> MrcResult<u64> {
+        let [nx, ny, nz] = self.dimensions();
+        let row = match self.data_mode()?.bytes_per_voxel() {
+            Some(bytes) => nx as u64 * bytes as u64,
+            // Rows of 4 bit voxels are padded to a whole byte
+            None => (nx as u64).div_ceil(2),
+        };
+        Ok(row * ny as u64 * nz as u64)
+    }
+
+    /// Numbers of the first column, row and section in the map (`nxstart`, `nystart`, `nzstart`)
+    pub fn start(&self) -> [i32; 3] {
+        [self.nxstart, self.nystart, self.nzstart]
+    }
+
+    /// Number of intervals along X, Y and Z of the "unit cell" (`mx`, `my`, `mz`)
+    pub fn sampling(&self) -> [usize; 3] {
+        // The sampling grid is checked to be non-negative when the header is read
+        [self.mx as usize, self.my as usize, self.mz as usize]
+    }
+
+    /// Cell lengths along X, Y and Z in angstroms (`xlen`, `ylen`, `zlen`)
+    pub fn cell_lengths(&self) -> [f32; 3] {
+        [self.xlen, self.ylen, self.zlen]
+    }
+
+    /// Cell angles alpha, beta and gamma in degrees
+    pub fn cell_angles(&self) -> [f32; 3] {
+        [self.alpha, self.beta, self.gama]
+    }
+
+    /// Number of intervals along X, Y and Z the cell is divided into
+    ///
+    /// This is the sampling, where it is missing the number of voxels along the axis and at least
+    /// one.
+    fn grid(&self) -> [f64; 3] {
+        let axes = self.axis_permutation().unwrap_or([0, 1, 2]);
+        let dimensions = self.dimensions();
+        let mut voxels = [0; 3];
+        for (&size, &axis) in dimensions.iter().zip(axes.iter()) {
+            voxels[axis] = size;
+        }
+        let mut grid = [1.0; 3];
+        for ((grid, &sampling), &voxels) in grid.iter_mut().zip(&self.sampling()).zip(&voxels) {
+            if sampling > 0 {
+                *grid = sampling as f64;
+            } else if voxels > 0 {
+                *grid = voxels as f64;
+            }
+        }
+        grid
+    }
+
+    /// Size of a voxel along X, Y and Z in angstroms
+    ///
+    /// This is the cell length divided by the sampling (`xlen / mx`, ...). Files that leave the
+    /// sampling zero are taken to sample the cell by their voxels. Files that leave the cell zero
+    /// have voxels of 1, i.e. their coordinates are in voxels.
+    pub fn voxel_size(&self) -> [f32; 3] {
+        let grid = self.grid();
+        let mut size = [1.0; 3];
+        for ((size, &length), &grid) in size.iter_mut().zip(&self.cell_lengths()).zip(&grid) {
+            if length > 0.0 {
+                *size = (length as f64 / grid) as f32;
+            }
+        }
+        size
+    }
+
+    /// Matrix transforming fractional coordinates of the cell into cartesian coordinates in
+    /// angstroms
+    ///
+    /// The a axis of the cell lies along X and the b axis in the XY plane. Missing cell lengths are
+    /// taken as in `voxel_size` and missing angles as 90 degrees.
+    pub fn fractional_to_cartesian(&self) -> [[f64; 3]; 3] {
+        let mut lengths = self.grid();
+        for (length, &cell) in lengths.iter_mut().zip(&self.cell_lengths()) {
+            if cell > 0.0 {
+                *length = cell as f64;
+            }
+        }
+        let [alpha, beta, gamma] = self.cell_angles().map(|angle| {
+            if angle > 0.0 && angle < 180.0 {
+                (angle as f64).to_radians()
+            } else {
+                std::f64::consts::FRAC_PI_2
+            }
+        });
+        let [a, b, c] = lengths;
+        let (cos_alpha, cos_beta, cos_gamma) = (alpha.cos(), beta.cos(), gamma.cos());
+        let sin_gamma = gamma.sin();
+        let volume = (1.0 - cos_alpha * cos_alpha - cos_beta * cos_beta - cos_gamma * cos_gamma
+            + 2.0 * cos_alpha * cos_beta * cos_gamma)
+            .max(0.0)
+            .sqrt();
+        [
+            [a, b * cos_gamma, c * cos_beta],
+            [
+                0.0,
+                b * sin_gamma,
+                c * (cos_alpha - cos_beta * cos_gamma) / sin_gamma,
+            ],
+            [0.0, 0.0, c * volume / sin_gamma],
+        ]
+    }
+
+    /// Position in angstroms of the voxel at `index` (column, row, section)
+    ///
+    /// The index may be fractional to address positions between the voxel centers. The columns,
+    /// rows and sections follow the axis mapping and the position is relative to the
+    /// `normalized_origin`, which accounts for the start indices.
+    pub fn index_to_world(&self, index: [f64; 3]) -> [f64; 3] {
+        let axes = self.axis_permutation().unwrap_or([0, 1, 2]);
+        let grid = self.grid();
+        let mut fractional = [0.0; 3];
+        for (&index, &axis) in index.iter().zip(axes.iter()) {
+            fractional[axis] = index / grid[axis];
+        }
+        let origin = self.normalized_origin().map(f64::from);
+        let mut world = transform(&self.fractional_to_cartesian(), fractional);
+        for (world, origin) in world.iter_mut().zip(&origin) {
+            *world += origin;
+        }
+        world
+    }
+
+    /// Index (column, row, section) of the position `world` in angstroms, the inverse of
+    /// `index_to_world`
+    ///
+    /// The index is fractional, it is rounded to get the nearest voxel.
+    pub fn world_to_index(&self, world: [f64; 3]) -> [f64; 3] {
+        let origin = self.normalized_origin().map(f64::from);
+        let mut relative = world;
+        for (relative, origin) in relative.iter_mut().zip(&origin) {
+            *relative -= origin;
+        }
+        // The matrix is upper triangular, so back substitution inverts it
+        let m = self.fractional_to_cartesian();
+        let z = relative[2] / m[2][2];
+        let y = (relative[1] - m[1][2] * z) / m[1][1];
+        let x = (relative[0] - m[0][1] * y - m[0][2] * z) / m[0][0];
+
+        let axes = self.axis_permutation().unwrap_or([0, 1, 2]);
+        let grid = self.grid();
+        let fractional = [x, y, z];
+        let mut index = [0.0; 3];
+        for (index, &axis) in index.iter_mut().zip(axes.iter()) {
+            *index = fractional[axis] * grid[axis];
+        }
+        index
+    }
+
+    /// Axes corresponding to columns, rows and sections (`mapc`, `mapr`, `maps`)
+    ///
+    /// 1 stands for X, 2 for Y and 3 for Z.
+    pub fn axis_mapping(&self) -> [i32; 3] {
+        [self.mapc, self.mapr, self.maps]
+    }
+
+    /// Axes of the columns, rows and sections counted from zero, i.e. 0 for X, 1 for Y and 2 for Z
+    ///
+    /// Fails unless `mapc`, `mapr` and `maps` are a permutation of 1, 2 and 3.
+    pub fn axis_permutation(&self) -> MrcResult<[usize; 3]> {
+        let mapping = self.axis_mapping();
+        let mut axes = [0; 3];
+        let mut seen = [false; 3];
+        for (axis, &mapped) in axes.iter_mut().zip(mapping.iter()) {
+            match mapped {
+                1..=3 if !seen[mapped as usize - 1] => {
+                    seen[mapped as usize - 1] = true;
+                    *axis = mapped as usize - 1;
+                }
+                _ => return Err(MrcFormatError::InvalidAxisMapping(mapping).into()),
+            }
+        }
+        Ok(axes)
+    }
+
+    /// The header of the volume reordered to X changing fastest and Z slowest
+    ///
+    /// The dimensions and the start indices are permuted from columns, rows and sections to X, Y
+    /// and Z and the axis mapping becomes 1, 2, 3. The sampling and the cell are given along X, Y
+    /// and Z in the first place and stay as they are.
+    pub fn canonical(&self) -> MrcResult<Header> {
+        let axes = self.axis_permutation()?;
+        let (file_dimensions, file_start) = ([self.nx, self.ny, self.nz], self.start());
+        let (mut dimensions, mut start) = ([0; 3], [0; 3]);
+        for (i, &axis) in axes.iter().enumerate() {
+            dimensions[axis] = file_dimensions[i];
+            start[axis] = file_start[i];
+        }
+
+        let mut header = self.clone();
+        [header.nx, header.ny, header.nz] = dimensions;
+        [header.nxstart, header.nystart, header.nzstart] = start;
+        [header.mapc, header.mapr, header.maps] = [1, 2, 3];
+        Ok(header)
+    }
+
+    /// Density statistics stored in the header
+    pub fn density_statistics(&self) -> DensityStatistics {
+        DensityStatistics {
+            min: self.amin,
+            max: self.amax,
+            mean: self.amean,
+            rms: self.rms,
+        }
+    }
+
+    /// Space group number (`ispg`)
+    ///
+    /// 0 for images and image stacks, 1 for single EM volumes, spacegroup + 400 for volume stacks.
+    pub fn space_group(&self) -> i32 {
+        self.ispg
+    }
+
+    /// Size of the extended header in bytes (`nsymbt`)
+    pub fn extended_header_size(&self) -> usize {
+        // `nsymbt` is checked to be non-negative when the header is read
+        self.nsymbt as usize
+    }
+
+    /// Kind of the extended header (`EXTTYP`), `None` if the field is blank
+    pub fn extended_header_type(&self) -> Option<ExtendedHeaderType> {
+        ExtendedHeaderType::from_code(&self.extra.ext_type)
+    }
+
+    /// Version of the MRC format (`NVERSION`), e.g. 20140
+    pub fn version(&self) -> i32 {
+        self.extra.nversion
+    }
+
+    /// Number of integers per section in the extended header (`nint`)
+    ///
+    /// For `SERI` extended headers, this is the number of bytes of each per-section record.
+    pub fn nint(&self) -> i16 {
+        self.extra.nint
+    }
+
+    /// Number of reals per section in the extended header (`nreal`)
+    ///
+    /// For `SERI` extended headers, these are the flags of the fields of the per-section records.
+    pub fn nreal(&self) -> i16 {
+        self.extra.nreal
+    }
+
+    /// Generation of the MRC format, detected from `nversion`, `imodStamp` and the `MAP ` tag
+    pub fn format_version(&self) -> FormatVersion {
+        if self.extra.nversion >= 20140 {
+            FormatVersion::Mrc2014
+        } else if self.software() == Some(Mode::IMOD) {
+            FormatVersion::Imod
+        } else if self.map == "MAP " {
+            FormatVersion::Mrc2000
+        } else {
+            FormatVersion::Legacy
+        }
+    }
+
+    /// Origin in X, Y and Z as stored in the header (`xorg`, `yorg`, `zorg`)
+    ///
+    /// Files written by Priism store the origin at bytes 209-220 in the order Z, X, Y and in the
+    /// units of the cell, usually microns. Their map tag, machine stamp and rms deviation are
+    /// undetermined.
+    pub fn origin(&self) -> [f32; 3] {
+        [self.origin.xorg, self.origin.yorg, self.origin.zorg]
+    }
+
+    /// Origin in X, Y and Z in angstroms following the MRC2014 conventions
+    ///
+    /// The origin of files written by Priism is in microns, as stored by Priism.
+    ///
+    /// IMOD files with the inverted origin flag have the sign of the origin flipped back. CCP4
+    /// maps and older files that leave the origin zero and position the map by the start indices
+    /// alone get the origin implied by `nxstart`, `nystart` and `nzstart` in grid units of the cell.
+    /// The start indices follow the axis mapping, an invalid mapping is taken as 1, 2, 3.
+    pub fn normalized_origin(&self) -> [f32; 3] {
+        let origin = self.origin();
+        if origin == [0.0; 3] && self.start() != [0; 3] {
+            let axes = self.axis_permutation().unwrap_or([0, 1, 2]);
+            let grid = self.grid();
+            let mut fractional = [0.0; 3];
+            for (&start, &axis) in self.start().iter().zip(axes.iter()) {
+                fractional[axis] = start as f64 / grid[axis];
+            }
+            return transform(&self.fractional_to_cartesian(), fractional).map(|n| n as f32);
+        }
+
+        match self.imod_flags() {
+            Some(flags) if flags.inverted_origin() => [-origin[0], -origin[1], -origin[2]],
+            _ => origin,
+        }
+    }
+
+    /// File type identifier, `"MAP "` for conforming files
+    pub fn map_tag(&self) -> &str {
+        &self.map
+    }
+
+    /// Machine stamp (`MACHST`)
+    pub fn machine_stamp(&self) -> [u8; 4] {
+        self.mach_st
+    }
+
+    /// Text labels in use, with the trailing padding removed
+    pub fn labels(&self) -> &[String] {
+        &self.label
+    }
+
+    /// Width and height of a single section (`nx`, `ny`)
+    pub(crate) fn image_dimensions(&self) -> (u32, u32) {
+        // `nx` and `ny` are checked to be non-negative when the header is read
+        (self.nx as u32, self.ny as u32)
+    }
+
+    /// Reads the 1024 byte main header from the current position of the `reader`
+    pub(crate) fn read<R: Read + Seek>(reader: &mut SmartReader<R>) -> MrcResult<Header> {
+        let nx = reader.read_i32()?;
+        let ny = reader.read_i32()?;
+        let nz = reader.read_i32()?;
+        if nx < 0 || ny < 0 || nz < 0 {
+            return Err(MrcFormatError::Format(format!(
+                "negative dimensions ({}, {}, {})",
+                nx, ny, nz
+            ))
+            .into());
+        }
+        let mode = reader.read_i32()?;
+
+        let nxstart = reader.read_i32()?;
+        let nystart = reader.read_i32()?;
+        let nzstart = reader.read_i32()?;
+
+        let mx = reader.read_i32()?;
+        let my = reader.read_i32()?;
+        let mz = reader.read_i32()?;
+        if mx < 0 || my < 0 || mz < 0 {
+            return Err(MrcFormatError::Format(format!(
+                "negative sampling grid ({}, {}, {})",
+                mx, my, mz
+            ))
+            .into());
+        }
+
+        let xlen = reader.read_f32()?;
+        let ylen = reader.read_f32()?;
+        let zlen = reader.read_f32()?;
+
+        let alpha = reader.read_f32()?;
+        let beta = reader.read_f32()?;
+        let gama = reader.read_f32()?;
+
+        let mapc = reader.read_i32()?;
+        let mapr = reader.read_i32()?;
+        let maps = reader.read_i32()?;
+
+        let amin = reader.read_f32()?;
+        let amax = reader.read_f32()?;
+        let amean = reader.read_f32()?;
+
+        let ispg = reader.read_i32()?;
+        let nsymbt = reader.read_i32()?;
+        if nsymbt < 0 {
+            return Err(MrcFormatError::Format(format!(
+                "negative extended header size {}",
+                nsymbt
+            ))
+            .into());
+        }
+
+        let extra = Extra::read(reader)?;
+
+        let origin = Origin {
+            xorg: reader.read_f32()?,
+            yorg: reader.read_f32()?,
+            zorg: reader.read_f32()?,
+        };
+
+        // Priism keeps the wavelengths in place of the origin and stores the origin (Z, X, Y) in
+        // place of the map tag, the machine stamp and the rms deviation, which are left
+        // undetermined
+        let (origin, map, mach_st, rms) = if extra.ive_id == IVE_ID {
+            let zorg = reader.read_f32()?;
+            let xorg = reader.read_f32()?;
+            let yorg = reader.read_f32()?;
+            (Origin { xorg, yorg, zorg }, String::new(), [0u8; 4], -1.0)
+        } else {
+            let map = read_ascii(reader, 4)?;
+            let mut mach_st = [0u8; 4];
+            reader.read_exact(&mut mach_st)?;
+            (origin, map, mach_st, reader.read_f32()?)
+        };
+
+        let nlabl = reader.read_i32()?;
+        let mut label = Vec::with_capacity(NUM_LABELS);
+        for _ in 0..NUM_LABELS {
+            label.push(read_ascii(reader, LABEL_SIZE)?.trim_end().to_string());
+        }
+        // Only the first `nlabl` labels are meaningful. Files that do not keep `nlabl` up to date
+        // are not rare, so an out-of-range value is clamped rather than rejected.
+        label.truncate(nlabl.clamp(0, NUM_LABELS as i32) as usize);
+
+        Ok(Header {
+            nx,
+            ny,
+            nz,
+            mode,
+            nxstart,
+            nystart,
+            nzstart,
+            mx,
+            my,
+            mz,
+            xlen,
+            ylen,
+            zlen,
+            alpha,
+            beta,
+            gama,
+            mapc,
+            mapr,
+            maps,
+            amin,
+            amax,
+            amean,
+            ispg,
+            nsymbt,
+            extra,
+            origin,
+            map,
+            mach_st,
+            rms,
+            label,
+        })
+    }
+}
+
+impl Header {
+    /// Replaces the density statistics (`amin`, `amax`, `amean`, `rms`)
+    pub(crate) fn set_density_statistics(&mut self, statistics: DensityStatistics) {
+        self.amin = statistics.min;
+        self.amax = statistics.max;
+        self.amean = statistics.mean;
+        self.rms = statistics.rms;
+    }
+
+    /// Marks the bytes of `Mode0` data as signed or unsigned
+    ///
+    /// Unsigned bytes are only expressible with the IMOD flags, so the IMOD stamp is set for them.
+    pub(crate) fn set_signed_bytes(&mut self, signed: bool) {
+        if signed {
+            self.extra.imod_flags |= 1;
+        } else {
+            self.extra.imod_stamp = IMOD_STAMP;
+            self.extra.imod_flags &= !1;
+        }
+    }
+
+    /// Overwrites only the density statistics of a header at the start of `writer`
+    ///
+    /// The rms deviation is not written for files written by Priism, which keep the origin in its
+    /// place.
+    #[cfg(feature = "mmap")]
+    pub(crate) fn write_density_statistics<W: Write + Seek>(
+        &self,
+        writer: &mut SmartWriter<W>,
+    ) -> MrcResult<()> {
+        writer.seek(std::io::SeekFrom::Start(DENSITY_OFFSET))?;
+        writer.write_f32_from(&[self.amin, self.amax, self.amean])?;
+        if self.extra.ive_id != IVE_ID {
+            writer.seek(std::io::SeekFrom::Start(RMS_OFFSET))?;
+            writer.write_f32(self.rms)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the 1024 byte main header in the byte order of the `writer`
+    pub(crate) fn write<W: Write + Seek>(&self, writer: &mut SmartWriter<W>) -> MrcResult<()> {
+        writer.write_i32_from(&[self.nx, self.ny, self.nz, self.mode])?;
+        writer.write_i32_from(&[
+            self.nxstart,
+            self.nystart,
+            self.nzstart,
+            self.mx,
+            self.my,
+            self.mz,
+        ])?;
+        writer.write_f32_from(&[
+            self.xlen, self.ylen, self.zlen, self.alpha, self.beta, self.gama,
+        ])?;
+        writer.write_i32_from(&[self.mapc, self.mapr, self.maps])?;
+        writer.write_f32_from(&[self.amin, self.amax, self.amean])?;
+        writer.write_i32_from(&[self.ispg, self.nsymbt])?;
+
+        writer.write_i16(self.extra.ive_id)?;
+        writer.write_all(&[0u8; 6])?; // 99-104
+        write_ascii(writer, &self.extra.ext_type, 4, 0)?;
+        writer.write_i32(self.extra.nversion)?;
+        writer.write_all(&[0u8; 16])?; // 113-128
+        writer.write_i16(self.extra.nint)?;
+        writer.write_i16(self.extra.nreal)?;
+        writer.write_all(&[0u8; 20])?; // 133-152
+        writer.write_i32(self.extra.imod_stamp)?;
+        writer.write_i32(self.extra.imod_flags)?;
+        writer.write_all(&[0u8; 36])?; // 161-196
+
+        writer.write_f32_from(&[self.origin.xorg, self.origin.yorg, self.origin.zorg])?;
+        write_ascii(writer, &self.map, 4, b' ')?;
+        writer.write_all(&self.mach_st)?;
+        writer.write_f32(self.rms)?;
+
+        let nlabl = self.label.len().min(NUM_LABELS);
+        writer.write_i32(nlabl as i32)?;
+        for label in &self.label[..nlabl] {
+            write_ascii(writer, label, LABEL_SIZE, b' ')?;
+        }
+        writer.write_all(&vec![0u8; (NUM_LABELS - nlabl) * LABEL_SIZE])?;
+        Ok(())
+    }
+}
+
+impl Extra {
+    /// Reads the 100 bytes of the `extra` area (bytes 97-196)
+    fn read<R: Read + Seek>(reader: &mut SmartReader<R>) -> MrcResult<Extra> {
+        let ive_id = reader.read_i16()?;
+        let mut unused = [0u8; 6];
+        reader.read_exact(&mut unused)?; // 99-104
+        let ext_type = read_ascii(reader, 4)?;
+        let nversion = reader.read_i32()?;
+        let mut unused = [0u8; 16];
+        reader.read_exact(&mut unused)?; // 113-128
+        let nint = reader.read_i16()?;
+        let nreal = reader.read_i16()?;
+        let mut unused = [0u8; 20];
+        reader.read_exact(&mut unused)?; // 133-152
+        let imod_stamp = reader.read_i32()?;
+        let imod_flags = reader.read_i32()?;
+        let mut unused = [0u8; 36];
+        reader.read_exact(&mut unused)?; // 161-196
+        Ok(Extra {
+            ive_id,
+            ext_type,
+            nversion,
+            nint,
+            nreal,
+            imod_stamp,
+            imod_flags,
+        })
+    }
+}
+
+/// Reads a fixed length character field and strips the trailing NUL padding
+fn read_ascii<R: Read>(reader: &mut R, len: usize) -> MrcResult<String> {
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf)?;
+    let end = buf.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    Ok(String::from_utf8_lossy(&buf[..end]).into_owned())
+}
+
+/// Writes a fixed length character field, truncating `value` or padding it with `padding`
+fn write_ascii<W: Write>(writer: &mut W, value: &str, len: usize, padding: u8) -> MrcResult<()> {
+    let mut buf = vec![padding; len];
+    let bytes = value.as_bytes();
+    let n = bytes.len().min(len);
+    buf[..n].copy_from_slice(&bytes[..n]);
+    writer.write_all(&buf)?;
+    Ok(())
+}
+
+/// Multiplies `vector` by `matrix`
+fn transform(matrix: &[[f64; 3]; 3], vector: [f64; 3]) -> [f64; 3] {
+    matrix.map(|row| row[0] * vector[0] + row[1] * vector[1] + row[2] * vector[2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::ByteOrder;
+    use crate::MrcError;
+    use std::io::Cursor;
+
+    fn set_i32(bytes: &mut [u8], offset: usize, value: i32) {
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn set_f32(bytes: &mut [u8], offset: usize, value: f32) {
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Little endian main header of a 4 x 3 x 2 `Mode2` volume with the axis mapping 1, 2, 3
+    fn header_bytes() -> Vec<u8> {
+        let mut bytes = vec![0u8; HEADER_SIZE as usize];
+        for (i, &word) in [4, 3, 2, 2].iter().enumerate() {
+            set_i32(&mut bytes, 4 * i, word);
+        }
+        for (i, &axis) in [1, 2, 3].iter().enumerate() {
+            set_i32(&mut bytes, 64 + 4 * i, axis);
+        }
+        bytes
+    }
+
+    fn read(bytes: &[u8]) -> Header {
+        Header::read(&mut SmartReader::wrap(
+            Cursor::new(bytes),
+            ByteOrder::LittleEndian,
+        ))
+        .unwrap()
+    }
+
+    fn with_axis_mapping(mapping: [i32; 3]) -> Header {
+        let mut bytes = header_bytes();
+        for (i, &axis) in mapping.iter().enumerate() {
+            set_i32(&mut bytes, 64 + 4 * i, axis);
+        }
+        read(&bytes)
+    }
+
+    #[test]
+    fn axis_permutations() {
+        for &(mapping, axes) in &[
+            ([1, 2, 3], [0, 1, 2]),
+            ([3, 1, 2], [2, 0, 1]),
+            ([2, 3, 1], [1, 2, 0]),
+            ([1, 3, 2], [0, 2, 1]),
+        ] {
+            assert_eq!(with_axis_mapping(mapping).axis_permutation().unwrap(), axes);
+        }
+        for &mapping in &[[1, 1, 3], [0, 2, 3], [1, 2, 4], [-1, 2, 3], [3, 3, 3]] {
+            match with_axis_mapping(mapping).axis_permutation() {
+                Err(MrcError::FormatError(MrcFormatError::InvalidAxisMapping(found))) => {
+                    assert_eq!(found, mapping)
+                }
+                result => panic!("unexpected {:?} for {:?}", result, mapping),
+            }
+        }
+    }
+
+    #[test]
+    fn canonical_header_of_permuted_axes() {
+        let mut bytes = header_bytes();
+        for (i, &word) in [1, 2, 3].iter().enumerate() {
+            set_i32(&mut bytes, 16 + 4 * i, word);
+        }
+        for (i, &axis) in [3, 1, 2].iter().enumerate() {
+            set_i32(&mut bytes, 64 + 4 * i, axis);
+        }
+        let canonical = read(&bytes).canonical().unwrap();
+        assert_eq!(canonical.dimensions(), [3, 2, 4]);
+        assert_eq!(canonical.start(), [2, 3, 1]);
+        assert_eq!(canonical.axis_mapping(), [1, 2, 3]);
+        assert!(with_axis_mapping([1, 2, 2]).canonical().is_err());
+    }
+    /// Header of a 4 x 3 x 2 volume with the given sampling, cell lengths and cell angles
+    fn with_cell(sampling: [i32; 3], lengths: [f32; 3], angles: [f32; 3]) -> Vec<u8> {
+        let mut bytes = header_bytes();
+        for i in 0..3 {
+            set_i32(&mut bytes, 28 + 4 * i, sampling[i]);
+            set_f32(&mut bytes, 40 + 4 * i, lengths[i]);
+            set_f32(&mut bytes, 52 + 4 * i, angles[i]);
+        }
+        bytes
+    }
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-9, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn voxel_size_without_sampling() {
+        // The cell is sampled by the voxels along X and Z
+        let header = read(&with_cell([0, 6, 0], [8.0, 12.0, 10.0], [90.0; 3]));
+        assert_eq!(header.voxel_size(), [2.0, 2.0, 5.0]);
+        let header = read(&with_cell([0; 3], [0.0; 3], [0.0; 3]));
+        assert_eq!(header.voxel_size(), [1.0; 3]);
+    }
+
+    #[test]
+    fn orthogonal_cell() {
+        let header = read(&with_cell([4, 3, 2], [8.0, 6.0, 4.0], [90.0; 3]));
+        assert_eq!(header.voxel_size(), [2.0; 3]);
+        let matrix = header.fractional_to_cartesian();
+        for (row, expected) in
+            matrix
+                .iter()
+                .zip(&[[8.0, 0.0, 0.0], [0.0, 6.0, 0.0], [0.0, 0.0, 4.0]])
+        {
+            assert_close(row, expected);
+        }
+        assert_close(&header.index_to_world([1.0, 2.0, 1.0]), &[2.0, 4.0, 2.0]);
+    }
+
+    #[test]
+    fn monoclinic_cell() {
+        // cos(120°) = -1/2, the c axis tilts back along X and keeps sin(120°) of its length in Z
+        let header = read(&with_cell(
+            [4, 3, 2],
+            [10.0, 20.0, 30.0],
+            [90.0, 120.0, 90.0],
+        ));
+        let expected = [
+            [10.0, 0.0, -15.0],
+            [0.0, 20.0, 0.0],
+            [0.0, 0.0, 15.0 * 3f64.sqrt()],
+        ];
+        for (row, expected) in header.fractional_to_cartesian().iter().zip(&expected) {
+            assert_close(row, expected);
+        }
+    }
+
+    #[test]
+    fn index_to_world_round_trip() {
+        // Without an origin, the start indices position the volume
+        let mut bytes = with_cell([4, 3, 2], [8.0, 6.0, 4.0], [90.0; 3]);
+        for (i, &start) in [2, 1, -1].iter().enumerate() {
+            set_i32(&mut bytes, 16 + 4 * i, start);
+        }
+        let started = read(&bytes);
+        assert_close(&started.index_to_world([0.0; 3]), &[4.0, 2.0, -2.0]);
+        assert_close(&started.index_to_world([1.0, 1.0, 1.0]), &[6.0, 4.0, 0.0]);
+
+        // An origin takes precedence over the start indices
+        for (i, &origin) in [100.0, 200.0, 300.0].iter().enumerate() {
+            set_f32(&mut bytes, 196 + 4 * i, origin);
+        }
+        let shifted = read(&bytes);
+        assert_close(
+            &shifted.index_to_world([1.0, 1.0, 1.0]),
+            &[102.0, 202.0, 302.0],
+        );
+
+        // A skewed cell with permuted axes, positioned by its start indices or by an origin
+        let mut bytes = with_cell([3, 2, 4], [10.0, 20.0, 30.0], [80.0, 110.0, 70.0]);
+        for i in 0..3 {
+            set_i32(&mut bytes, 16 + 4 * i, [3, -2, 5][i]);
+            set_i32(&mut bytes, 64 + 4 * i, [3, 1, 2][i]);
+        }
+        let skewed = read(&bytes);
+        for (i, &origin) in [1.5, -2.5, 7.0].iter().enumerate() {
+            set_f32(&mut bytes, 196 + 4 * i, origin);
+        }
+        let skewed_shifted = read(&bytes);
+        for header in &[started, shifted, skewed, skewed_shifted] {
+            for &index in &[[0.0; 3], [1.0, 2.0, 1.0], [3.5, 0.25, 1.75]] {
+                let world = header.index_to_world(index);
+                assert_close(&header.world_to_index(world), &index);
+            }
+        }
+    }
+    #[test]
+    fn known_header() {
+        let mut bytes = vec![0u8; HEADER_SIZE as usize];
+        let words: [(usize, i32); 14] = [
+            (0, 64),
+            (4, 32),
+            (8, 16),
+            (12, 2),
+            (16, -32),
+            (20, -16),
+            (24, -8),
+            (28, 64),
+            (32, 32),
+            (36, 16),
+            (64, 2),
+            (68, 1),
+            (72, 3),
+            (88, 1),
+        ];
+        for &(offset, word) in &words {
+            set_i32(&mut bytes, offset, word);
+        }
+        let floats: [(usize, f32); 13] = [
+            (40, 128.0),
+            (44, 64.0),
+            (48, 32.0),
+            (52, 90.0),
+            (56, 90.0),
+            (60, 120.0),
+            (76, -1.0),
+            (80, 2.0),
+            (84, 0.5),
+            (196, 1.0),
+            (200, 2.0),
+            (204, 3.0),
+            (216, 0.25),
+        ];
+        for &(offset, value) in &floats {
+            set_f32(&mut bytes, offset, value);
+        }
+        set_i32(&mut bytes, 92, 160);
+        bytes[104..108].copy_from_slice(b"MRCO");
+        set_i32(&mut bytes, 108, 20140);
+        bytes[208..212].copy_from_slice(b"MAP ");
+        bytes[212..216].copy_from_slice(&[0x44, 0x44, 0, 0]);
+        set_i32(&mut bytes, 220, 2);
+        bytes[224..234].copy_from_slice(b"first     ");
+        bytes[304..310].copy_from_slice(b"second");
+        bytes[384..389].copy_from_slice(b"stale");
+
+        let header = read(&bytes);
+        assert_eq!(header.dimensions(), [64, 32, 16]);
+        assert_eq!(header.mode().unwrap(), Mode::Mode2);
+        assert_eq!(header.start(), [-32, -16, -8]);
+        assert_eq!(header.sampling(), [64, 32, 16]);
+        assert_eq!(header.cell_lengths(), [128.0, 64.0, 32.0]);
+        assert_eq!(header.cell_angles(), [90.0, 90.0, 120.0]);
+        assert_eq!(header.axis_mapping(), [2, 1, 3]);
+        let statistics = header.density_statistics();
+        assert_eq!(statistics.range(), Some((-1.0, 2.0)));
+        assert_eq!(statistics.mean(), Some(0.5));
+        assert_eq!(statistics.rms(), Some(0.25));
+        assert_eq!(header.space_group(), 1);
+        assert_eq!(header.extended_header_size(), 160);
+        assert_eq!(
+            header.extended_header_type(),
+            Some(ExtendedHeaderType::MRCO)
+        );
+        assert_eq!(header.version(), 20140);
+        assert_eq!(header.origin(), [1.0, 2.0, 3.0]);
+        assert_eq!(header.map_tag(), "MAP ");
+        assert_eq!(header.machine_stamp(), [0x44, 0x44, 0, 0]);
+        // Labels beyond `nlabl` are dropped, trailing spaces are trimmed
+        assert_eq!(header.labels(), ["first", "second"]);
+    }
+
+    #[test]
+    fn negative_dimensions_are_rejected() {
+        let mut bytes = header_bytes();
+        set_i32(&mut bytes, 4, -3);
+        let result = Header::read(&mut SmartReader::wrap(
+            Cursor::new(&bytes),
+            ByteOrder::LittleEndian,
+        ));
+        assert!(result.is_err());
+    }
+    /// Header of `Mode0` data with the origin 1, 2, 3, stamped by IMOD with `flags` if `imod`
+    fn imod_header(imod: bool, flags: i32) -> Header {
+        let mut bytes = header_bytes();
+        set_i32(&mut bytes, 12, 0);
+        if imod {
+            set_i32(&mut bytes, 152, IMOD_STAMP);
+        }
+        set_i32(&mut bytes, 156, flags);
+        for (i, &origin) in [1.0, 2.0, 3.0].iter().enumerate() {
+            set_f32(&mut bytes, 196 + 4 * i, origin);
+        }
+        read(&bytes)
+    }
+
+    #[test]
+    fn imod_stamp_and_flags() {
+        // Flags are ignored without the stamp
+        let header = imod_header(false, 1 | 4 | 16);
+        assert_eq!(header.software(), None);
+        assert_eq!(header.imod_flags(), None);
+        assert!(header.signed_bytes());
+        assert_eq!(header.data_mode().unwrap(), Mode::Mode0);
+        assert_eq!(header.normalized_origin(), [1.0, 2.0, 3.0]);
+
+        // Bytes of older IMOD files are unsigned
+        let header = imod_header(true, 0);
+        assert_eq!(header.software(), Some(Mode::IMOD));
+        assert_eq!(header.imod_flags(), Some(ImodFlags(0)));
+        assert!(!header.signed_bytes());
+
+        let flags = imod_header(true, 1).imod_flags().unwrap();
+        assert!(flags.signed_bytes());
+        assert!(imod_header(true, 1).signed_bytes());
+        assert!(!flags.pixel_spacing_from_extended_header() && !flags.inverted_origin());
+
+        let flags = imod_header(true, 2).imod_flags().unwrap();
+        assert!(flags.pixel_spacing_from_extended_header());
+        assert!(!flags.signed_bytes() && !flags.inverted_origin());
+
+        let header = imod_header(true, 4);
+        assert!(header.imod_flags().unwrap().inverted_origin());
+        assert_eq!(header.origin(), [1.0, 2.0, 3.0]);
+        assert_eq!(header.normalized_origin(), [-1.0, -2.0, -3.0]);
+
+        // Rows of 4 voxels packed into 2 bytes
+        let header = imod_header(true, 16);
+        assert!(header.imod_flags().unwrap().packed_nibbles());
+        assert_eq!(header.mode().unwrap(), Mode::Mode0);
+        assert_eq!(header.data_mode().unwrap(), Mode::Mode101);
+        assert_eq!(header.data_size().unwrap(), 2 * 3 * 2);
+    }
+    #[test]
+    fn format_versions() {
+        for &(nversion, imod, tag, expected) in &[
+            (20140, false, b"MAP ", FormatVersion::Mrc2014),
+            (20141, true, b"MAP ", FormatVersion::Mrc2014),
+            (0, true, b"MAP ", FormatVersion::Imod),
+            (0, true, b"\0\0\0\0", FormatVersion::Imod),
+            (0, false, b"MAP ", FormatVersion::Mrc2000),
+            (1000, false, b"MAP ", FormatVersion::Mrc2000),
+            (0, false, b"\0\0\0\0", FormatVersion::Legacy),
+            (0, false, b"map ", FormatVersion::Legacy),
+        ] {
+            let mut bytes = header_bytes();
+            set_i32(&mut bytes, 108, nversion);
+            if imod {
+                set_i32(&mut bytes, 152, IMOD_STAMP);
+            }
+            bytes[208..212].copy_from_slice(tag);
+            assert_eq!(read(&bytes).format_version(), expected);
+        }
+    }
+
+    #[test]
+    fn origin_of_the_start_indices() {
+        // Columns along Y, rows along X, in a cell of 2 angstrom voxels
+        let mut bytes = with_cell([4, 3, 2], [8.0, 6.0, 4.0], [90.0; 3]);
+        for i in 0..3 {
+            set_i32(&mut bytes, 16 + 4 * i, [1, 3, -1][i]);
+            set_i32(&mut bytes, 64 + 4 * i, [2, 1, 3][i]);
+        }
+        assert_eq!(read(&bytes).normalized_origin(), [6.0, 2.0, -2.0]);
+    }
 }
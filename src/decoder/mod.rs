@@ -1,15 +1,21 @@
-use super::{Mode, MrcError, MrcResult};
+use super::{Mode, MrcError, MrcFormatError, MrcResult, MrcUnsupportedError, MrcUsageError};
 use std::cmp;
-use std::collections::HashMap;
-use std::convert::TryFrom;
-use std::io::{self, Read, Seek};
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
 
 pub mod header;
 pub mod ifd;
-mod stream;
+pub mod stream;
 
-use self::stream::{ByteOrder, SmartReader};
-use header::Header;
+pub use self::stream::ByteOrder;
+use self::stream::{EndianReader, SmartReader};
+use crate::extended::ccp4::{self, SymmetryOperator};
+use crate::extended::fei::{self, FeiMetadata};
+use crate::extended::ive::{self, IveHeader, IveSection};
+use crate::extended::seri::{self, SerialEmRecord};
+use header::{
+    ExtendedHeaderType, Header, HEADER_SIZE, IVE_ID, IVE_ID_OFFSET, MACHINE_STAMP_OFFSET,
+};
 
 /// Result of a decoding process
 #[derive(Debug)]
@@ -26,10 +32,26 @@ pub enum DecodingResult {
     F32(Vec<f32>),
     /// A vector of 64 bit IEEE floats
     F64(Vec<f64>),
+    /// A vector of 16 bit IEEE floats, stored as their bit patterns
+    F16(Vec<u16>),
+    /// A vector of signed bytes
+    I8(Vec<i8>),
+    /// A vector of signed words
+    I16(Vec<i16>),
+    /// A vector of 32 bit signed ints
+    I32(Vec<i32>),
+    /// A vector of complex numbers with signed word components, interleaved as real, imaginary
+    ComplexI16(Vec<i16>),
+    /// A vector of complex numbers with 32 bit IEEE float components, interleaved as real,
+    /// imaginary
+    ComplexF32(Vec<f32>),
 }
 
+/// The constructors allocate a zeroed result of `size` elements, failing if it exceeds
+/// `Limits::decoding_buffer_size`. A result allocated once can be reused for every section through
+/// `as_buffer` and `Decoder::read_next_section_into`.
 impl DecodingResult {
-    fn new_u8(size: usize, limits: &Limits) -> MrcResult<DecodingResult> {
+    pub fn new_u8(size: usize, limits: &Limits) -> MrcResult<DecodingResult> {
         if size > limits.decoding_buffer_size {
             Err(MrcError::LimitsExceeded)
         } else {
@@ -37,7 +59,7 @@ impl DecodingResult {
         }
     }
 
-    fn new_u16(size: usize, limits: &Limits) -> MrcResult<DecodingResult> {
+    pub fn new_u16(size: usize, limits: &Limits) -> MrcResult<DecodingResult> {
         if size > limits.decoding_buffer_size / 2 {
             Err(MrcError::LimitsExceeded)
         } else {
@@ -45,7 +67,7 @@ impl DecodingResult {
         }
     }
 
-    fn new_u32(size: usize, limits: &Limits) -> MrcResult<DecodingResult> {
+    pub fn new_u32(size: usize, limits: &Limits) -> MrcResult<DecodingResult> {
         if size > limits.decoding_buffer_size / 4 {
             Err(MrcError::LimitsExceeded)
         } else {
@@ -53,7 +75,7 @@ impl DecodingResult {
         }
     }
 
-    fn new_u64(size: usize, limits: &Limits) -> MrcResult<DecodingResult> {
+    pub fn new_u64(size: usize, limits: &Limits) -> MrcResult<DecodingResult> {
         if size > limits.decoding_buffer_size / 8 {
             Err(MrcError::LimitsExceeded)
         } else {
@@ -61,7 +83,7 @@ impl DecodingResult {
         }
     }
 
-    fn new_f32(size: usize, limits: &Limits) -> MrcResult<DecodingResult> {
+    pub fn new_f32(size: usize, limits: &Limits) -> MrcResult<DecodingResult> {
         if size > limits.decoding_buffer_size / std::mem::size_of::<f32>() {
             Err(MrcError::LimitsExceeded)
         } else {
@@ -69,7 +91,7 @@ impl DecodingResult {
         }
     }
 
-    fn new_f64(size: usize, limits: &Limits) -> MrcResult<DecodingResult> {
+    pub fn new_f64(size: usize, limits: &Limits) -> MrcResult<DecodingResult> {
         if size > limits.decoding_buffer_size / std::mem::size_of::<f64>() {
             Err(MrcError::LimitsExceeded)
         } else {
@@ -77,7 +99,57 @@ impl DecodingResult {
         }
     }
 
-    pub fn as_buffer(&mut self, start: usize) -> DecodingBuffer {
+    pub fn new_f16(size: usize, limits: &Limits) -> MrcResult<DecodingResult> {
+        if size > limits.decoding_buffer_size / 2 {
+            Err(MrcError::LimitsExceeded)
+        } else {
+            Ok(DecodingResult::F16(vec![0; size]))
+        }
+    }
+
+    pub fn new_i8(size: usize, limits: &Limits) -> MrcResult<DecodingResult> {
+        if size > limits.decoding_buffer_size {
+            Err(MrcError::LimitsExceeded)
+        } else {
+            Ok(DecodingResult::I8(vec![0; size]))
+        }
+    }
+
+    pub fn new_i16(size: usize, limits: &Limits) -> MrcResult<DecodingResult> {
+        if size > limits.decoding_buffer_size / 2 {
+            Err(MrcError::LimitsExceeded)
+        } else {
+            Ok(DecodingResult::I16(vec![0; size]))
+        }
+    }
+
+    pub fn new_i32(size: usize, limits: &Limits) -> MrcResult<DecodingResult> {
+        if size > limits.decoding_buffer_size / 4 {
+            Err(MrcError::LimitsExceeded)
+        } else {
+            Ok(DecodingResult::I32(vec![0; size]))
+        }
+    }
+
+    /// `size` is the number of complex numbers
+    pub fn new_complex_i16(size: usize, limits: &Limits) -> MrcResult<DecodingResult> {
+        if size > limits.decoding_buffer_size / (2 * std::mem::size_of::<i16>()) {
+            Err(MrcError::LimitsExceeded)
+        } else {
+            Ok(DecodingResult::ComplexI16(vec![0; 2 * size]))
+        }
+    }
+
+    /// `size` is the number of complex numbers
+    pub fn new_complex_f32(size: usize, limits: &Limits) -> MrcResult<DecodingResult> {
+        if size > limits.decoding_buffer_size / (2 * std::mem::size_of::<f32>()) {
+            Err(MrcError::LimitsExceeded)
+        } else {
+            Ok(DecodingResult::ComplexF32(vec![0.0; 2 * size]))
+        }
+    }
+
+    pub fn as_buffer(&mut self, start: usize) -> DecodingBuffer<'_> {
         match *self {
             DecodingResult::U8(ref mut buf) => DecodingBuffer::U8(&mut buf[start..]),
             DecodingResult::U16(ref mut buf) => DecodingBuffer::U16(&mut buf[start..]),
@@ -85,6 +157,16 @@ impl DecodingResult {
             DecodingResult::U64(ref mut buf) => DecodingBuffer::U64(&mut buf[start..]),
             DecodingResult::F32(ref mut buf) => DecodingBuffer::F32(&mut buf[start..]),
             DecodingResult::F64(ref mut buf) => DecodingBuffer::F64(&mut buf[start..]),
+            DecodingResult::F16(ref mut buf) => DecodingBuffer::F16(&mut buf[start..]),
+            DecodingResult::I8(ref mut buf) => DecodingBuffer::I8(&mut buf[start..]),
+            DecodingResult::I16(ref mut buf) => DecodingBuffer::I16(&mut buf[start..]),
+            DecodingResult::I32(ref mut buf) => DecodingBuffer::I32(&mut buf[start..]),
+            DecodingResult::ComplexI16(ref mut buf) => {
+                DecodingBuffer::ComplexI16(&mut buf[start..])
+            }
+            DecodingResult::ComplexF32(ref mut buf) => {
+                DecodingBuffer::ComplexF32(&mut buf[start..])
+            }
         }
     }
 }
@@ -103,6 +185,19 @@ pub enum DecodingBuffer<'a> {
     F32(&'a mut [f32]),
     /// A slice of 64 bit IEEE floats
     F64(&'a mut [f64]),
+    /// A slice of 16 bit IEEE floats, stored as their bit patterns
+    F16(&'a mut [u16]),
+    /// A slice of signed bytes
+    I8(&'a mut [i8]),
+    /// A slice of signed words
+    I16(&'a mut [i16]),
+    /// A slice of 32 bit signed ints
+    I32(&'a mut [i32]),
+    /// A slice of complex numbers with signed word components, interleaved as real, imaginary
+    ComplexI16(&'a mut [i16]),
+    /// A slice of complex numbers with 32 bit IEEE float components, interleaved as real,
+    /// imaginary
+    ComplexF32(&'a mut [f32]),
 }
 
 impl<'a> DecodingBuffer<'a> {
@@ -114,6 +209,12 @@ impl<'a> DecodingBuffer<'a> {
             DecodingBuffer::U64(ref buf) => buf.len(),
             DecodingBuffer::F32(ref buf) => buf.len(),
             DecodingBuffer::F64(ref buf) => buf.len(),
+            DecodingBuffer::F16(ref buf) => buf.len(),
+            DecodingBuffer::I8(ref buf) => buf.len(),
+            DecodingBuffer::I16(ref buf) => buf.len(),
+            DecodingBuffer::I32(ref buf) => buf.len(),
+            DecodingBuffer::ComplexI16(ref buf) => buf.len(),
+            DecodingBuffer::ComplexF32(ref buf) => buf.len(),
         }
     }
 
@@ -125,10 +226,19 @@ impl<'a> DecodingBuffer<'a> {
             DecodingBuffer::U64(_) => 8,
             DecodingBuffer::F32(_) => 4,
             DecodingBuffer::F64(_) => 8,
+            DecodingBuffer::F16(_) => 2,
+            DecodingBuffer::I8(_) => 1,
+            DecodingBuffer::I16(_) => 2,
+            DecodingBuffer::I32(_) => 4,
+            DecodingBuffer::ComplexI16(_) => 2,
+            DecodingBuffer::ComplexF32(_) => 4,
         }
     }
 
-    fn copy<'b>(&'b mut self) -> DecodingBuffer<'b>
+    /// Reborrows the buffer for a call that takes it by value
+    ///
+    /// This decodes several sections into the same buffer with `read_next_section_into`.
+    pub fn copy<'b>(&'b mut self) -> DecodingBuffer<'b>
     where
         'a: 'b,
     {
@@ -139,15 +249,60 @@ impl<'a> DecodingBuffer<'a> {
             DecodingBuffer::U64(ref mut buf) => DecodingBuffer::U64(buf),
             DecodingBuffer::F32(ref mut buf) => DecodingBuffer::F32(buf),
             DecodingBuffer::F64(ref mut buf) => DecodingBuffer::F64(buf),
+            DecodingBuffer::F16(ref mut buf) => DecodingBuffer::F16(buf),
+            DecodingBuffer::I8(ref mut buf) => DecodingBuffer::I8(buf),
+            DecodingBuffer::I16(ref mut buf) => DecodingBuffer::I16(buf),
+            DecodingBuffer::I32(ref mut buf) => DecodingBuffer::I32(buf),
+            DecodingBuffer::ComplexI16(ref mut buf) => DecodingBuffer::ComplexI16(buf),
+            DecodingBuffer::ComplexF32(ref mut buf) => DecodingBuffer::ComplexF32(buf),
+        }
+    }
+
+    /// The elements `start..end` of the buffer
+    fn segment(&mut self, start: usize, end: usize) -> DecodingBuffer<'_> {
+        match *self {
+            DecodingBuffer::U8(ref mut buf) => DecodingBuffer::U8(&mut buf[start..end]),
+            DecodingBuffer::U16(ref mut buf) => DecodingBuffer::U16(&mut buf[start..end]),
+            DecodingBuffer::U32(ref mut buf) => DecodingBuffer::U32(&mut buf[start..end]),
+            DecodingBuffer::U64(ref mut buf) => DecodingBuffer::U64(&mut buf[start..end]),
+            DecodingBuffer::F32(ref mut buf) => DecodingBuffer::F32(&mut buf[start..end]),
+            DecodingBuffer::F64(ref mut buf) => DecodingBuffer::F64(&mut buf[start..end]),
+            DecodingBuffer::F16(ref mut buf) => DecodingBuffer::F16(&mut buf[start..end]),
+            DecodingBuffer::I8(ref mut buf) => DecodingBuffer::I8(&mut buf[start..end]),
+            DecodingBuffer::I16(ref mut buf) => DecodingBuffer::I16(&mut buf[start..end]),
+            DecodingBuffer::I32(ref mut buf) => DecodingBuffer::I32(&mut buf[start..end]),
+            DecodingBuffer::ComplexI16(ref mut buf) => {
+                DecodingBuffer::ComplexI16(&mut buf[start..end])
+            }
+            DecodingBuffer::ComplexF32(ref mut buf) => {
+                DecodingBuffer::ComplexF32(&mut buf[start..end])
+            }
         }
     }
+
+    /// Whether the buffer can hold data decoded from `mode`
+    fn fits(&self, mode: Mode) -> bool {
+        matches!(
+            (mode, self),
+            (Mode::Mode0, DecodingBuffer::I8(_))
+                | (Mode::Mode0, DecodingBuffer::U8(_))
+                | (Mode::Mode1, DecodingBuffer::I16(_))
+                | (Mode::Mode2, DecodingBuffer::F32(_))
+                | (Mode::Mode3, DecodingBuffer::ComplexI16(_))
+                | (Mode::Mode4, DecodingBuffer::ComplexF32(_))
+                | (Mode::Mode6, DecodingBuffer::U16(_))
+                | (Mode::Mode12, DecodingBuffer::F16(_))
+                | (Mode::Mode12, DecodingBuffer::F32(_))
+                | (Mode::Mode16, DecodingBuffer::U8(_))
+                | (Mode::Mode101, DecodingBuffer::U8(_))
+        )
+    }
 }
 
 #[derive(Debug)]
-struct StripDecodeState {
-    strip_index: usize,
-    strip_offsets: Vec<u64>,
-    strip_bytes: Vec<u64>,
+struct SectionDecodeState {
+    /// Index of the next section to be decoded
+    section_index: usize,
 }
 
 /// Decoding limits
@@ -155,8 +310,8 @@ struct StripDecodeState {
 pub struct Limits {
     /// The maximum size of any `DecodingResult` in bytes, the default is
     /// 256MiB. If the entire image is decoded at once, then this will
-    /// be the maximum size of the image. If it is decoded one strip at a
-    /// time, this will be the maximum size of a strip.
+    /// be the maximum size of the image. If it is decoded one section at a
+    /// time, this will be the maximum size of a section.
     pub decoding_buffer_size: usize,
     /// The maximum size of any ifd value in bytes, the default is
     /// 1MiB.
@@ -188,10 +343,14 @@ where
     width: u32,
     height: u32,
     header: Option<Header>,
+    native_half: bool,
+    canonical_axes: bool,
+    /// Header with the axes reordered to X, Y, Z, see `with_canonical_axes`
+    canonical_header: Option<Header>,
     // bits_per_sample: Vec<u8>,
     // samples: u8,
     // sample_format: Vec<SampleFormat>,
-    strip_decoder: Option<StripDecodeState>,
+    section_decoder: Option<SectionDecodeState>,
 }
 
 impl<R: Read + Seek> Decoder<R> {
@@ -204,11 +363,14 @@ impl<R: Read + Seek> Decoder<R> {
             width: 0,
             height: 0,
             header: None,
+            native_half: false,
+            canonical_axes: false,
+            canonical_header: None,
             // bits_per_sample: vec![1],
             // samples: 1,
             // sample_format: vec![SampleFormat::Uint],
             // photometric_interpretation: PhotometricInterpretation::BlackIsZero,
-            strip_decoder: None,
+            section_decoder: None,
         }
         .init()
     }
@@ -218,27 +380,1220 @@ impl<R: Read + Seek> Decoder<R> {
         self
     }
 
+    /// Decode half-precision data (`Mode12`) into `DecodingResult::F16` instead of widening it to
+    /// `DecodingResult::F32`
+    pub fn with_native_half(mut self, native_half: bool) -> Decoder<R> {
+        self.native_half = native_half;
+        self
+    }
+
+    /// Decode the voxels ordered with X changing fastest and Z slowest, whatever the axis mapping
+    /// (`mapc`, `mapr`, `maps`) of the file
+    ///
+    /// The dimensions and the start indices of `header` are then permuted accordingly, see
+    /// `Header::canonical`. The sections and regions are given along X, Y and Z as well. Files
+    /// whose axis mapping is not a permutation fail to decode.
+    pub fn with_canonical_axes(mut self, canonical_axes: bool) -> Decoder<R> {
+        self.canonical_axes = canonical_axes;
+        self.canonical_header = self
+            .header
+            .as_ref()
+            .and_then(|header| header.canonical().ok());
+        if let Ok(header) = self.volume_header() {
+            let (width, height) = header.image_dimensions();
+            self.width = width;
+            self.height = height;
+        }
+        self
+    }
+
     pub fn dimensions(&self) -> MrcResult<(u32, u32)> {
         Ok((self.width, self.height))
     }
 
+    /// The main header of the file
+    ///
+    /// With `with_canonical_axes`, this is the header of the reordered volume, which fails for
+    /// files whose axis mapping is not a permutation.
+    pub fn header(&self) -> MrcResult<&Header> {
+        self.volume_header()
+    }
+
+    /// Byte order of the file
+    pub fn byte_order(&self) -> ByteOrder {
+        self.byte_order
+    }
+
+    /// Reads the raw bytes of the extended header, which follows the main header
+    pub fn read_extended_header(&mut self) -> MrcResult<Vec<u8>> {
+        let size = self.get_header()?.extended_header_size();
+        if size > self.limits.decoding_buffer_size {
+            return Err(MrcError::LimitsExceeded);
+        }
+        let mut bytes = vec![0; size];
+        self.reader.seek(SeekFrom::Start(HEADER_SIZE))?;
+        self.reader.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Checks that the extended header is of type `expected`
+    fn check_extended_header_type(&self, expected: &[ExtendedHeaderType]) -> MrcResult<()> {
+        let found = self.get_header()?.extended_header_type();
+        match found {
+            Some(ref found) if expected.contains(found) => Ok(()),
+            _ => Err(MrcUsageError::ExtendedHeaderMismatch {
+                expected: expected[0].clone(),
+                found,
+            }
+            .into()),
+        }
+    }
+
+    /// Symmetry operators of the space group from the records of a crystallographic map
+    ///
+    /// Reads the records of an extended header of type `CCP4`. An extended header without a type
+    /// is read as records if it is made up of valid ones and gives an empty list otherwise, as it
+    /// often holds other metadata, e.g. of older FEI files.
+    pub fn symmetry_operators(&mut self) -> MrcResult<Vec<SymmetryOperator>> {
+        let header = self.get_header()?;
+        let size = header.extended_header_size();
+        if header.extended_header_type().is_none() {
+            if size == 0 || !size.is_multiple_of(ccp4::RECORD_SIZE) {
+                return Ok(Vec::new());
+            }
+            let bytes = self.read_extended_header()?;
+            return Ok(ccp4::read_records(&bytes).unwrap_or_default());
+        }
+        self.check_extended_header_type(&[ExtendedHeaderType::CCP4])?;
+        let bytes = self.read_extended_header()?;
+        ccp4::read_records(&bytes)
+    }
+
+    /// Metadata of each section from an extended header of type `FEI1` or `FEI2`
+    ///
+    /// The list is in the order of the sections in the file. It is shorter than the number of
+    /// sections if the extended header does not hold a block for each of them.
+    pub fn fei_metadata(&mut self) -> MrcResult<Vec<FeiMetadata>> {
+        self.check_extended_header_type(&[ExtendedHeaderType::FEI1, ExtendedHeaderType::FEI2])?;
+        let sections = self.get_header()?.dimensions()[2];
+        let bytes = self.read_extended_header()?;
+        fei::read_blocks(&bytes, self.byte_order, sections)
+    }
+
+    /// Records of each section from an extended header of type `SERI`
+    ///
+    /// Files written by IMOD before the extended header type was introduced leave it blank; their
+    /// records are read if `nint` matches the size of the fields flagged in `nreal`. The list is
+    /// in the order of the sections in the file and shorter than the number of sections if the
+    /// extended header does not hold a record for each of them.
+    pub fn serialem_records(&mut self) -> MrcResult<Vec<SerialEmRecord>> {
+        let header = self.get_header()?;
+        let untyped = header.extended_header_type().is_none()
+            && header.software() == Some(Mode::IMOD)
+            && seri::matches_flags(header.nint(), header.nreal());
+        if !untyped {
+            self.check_extended_header_type(&[ExtendedHeaderType::SERI])?;
+        }
+        let header = self.get_header()?;
+        let (sections, nint, nreal) = (header.dimensions()[2], header.nint(), header.nreal());
+        let bytes = self.read_extended_header()?;
+        seri::read_records(&bytes, self.byte_order, sections, nint, nreal)
+    }
+
+    /// Checks that the file was written by Priism, identified by `nDVID`
+    fn check_ive(&self) -> MrcResult<()> {
+        match self.get_header()?.software() {
+            Some(Mode::IVE) => Ok(()),
+            found => Err(MrcUsageError::SoftwareMismatch {
+                expected: Mode::IVE,
+                found,
+            }
+            .into()),
+        }
+    }
+
+    /// Priism (IVE) layout of the file: wavelengths, timepoints and their interleaving in `nz`
+    ///
+    /// Requires a file written by Priism or DeltaVision software, identified by `nDVID`, as the
+    /// layout is stored in the place of fields of the MRC header.
+    pub fn ive_header(&mut self) -> MrcResult<IveHeader> {
+        self.check_ive()?;
+        let sections = self.get_header()?.dimensions()[2];
+        let mut bytes = [0u8; HEADER_SIZE as usize];
+        self.reader.seek(SeekFrom::Start(0))?;
+        self.reader.read_exact(&mut bytes)?;
+        ive::read_header(&bytes, self.byte_order, sections)
+    }
+
+    /// Integers and floats of each section from a Priism (`AGAR`) extended header
+    ///
+    /// Each section holds `nint` integers followed by `nreal` floats. The list is in the order of
+    /// the sections in the file and shorter than the number of sections if the extended header
+    /// does not hold a record for each of them.
+    ///
+    /// Requires a file written by Priism or of extended header type `AGAR`.
+    pub fn ive_sections(&mut self) -> MrcResult<Vec<IveSection>> {
+        if self.check_ive().is_err() {
+            self.check_extended_header_type(&[ExtendedHeaderType::AGAR])?;
+        }
+        let header = self.get_header()?;
+        let (sections, nint, nreal) = (header.dimensions()[2], header.nint(), header.nreal());
+        let bytes = self.read_extended_header()?;
+        ive::read_sections(&bytes, self.byte_order, sections, nint, nreal)
+    }
+
+    /// Decodes the focal planes of `channel` at `timepoint` of a Priism file as a single volume
+    ///
+    /// The layout of the result is the one of `read_volume` for a volume of `nz` divided by the
+    /// number of channels and timepoints sections, see `ive_header`. The sections are read as
+    /// stored in the file, as the interleaving refers to the sections of the file, so
+    /// `with_canonical_axes` does not apply.
+    pub fn read_ive_stack(
+        &mut self,
+        channel: usize,
+        timepoint: usize,
+    ) -> MrcResult<DecodingResult> {
+        let layout = self.ive_header()?;
+        for &(axis, index, size) in &[
+            ('w', channel, layout.channels),
+            ('t', timepoint, layout.timepoints),
+        ] {
+            if index >= size {
+                return Err(MrcUsageError::RegionOutOfBounds {
+                    axis,
+                    start: index,
+                    end: index.saturating_add(1),
+                    size,
+                }
+                .into());
+            }
+        }
+        let header = self.get_header()?;
+        let mode = header.data_mode()?;
+        let [nx, ny, _] = header.dimensions();
+        let voxels = nx
+            .checked_mul(ny)
+            .and_then(|n| n.checked_mul(layout.focal_planes))
+            .ok_or(MrcError::LimitsExceeded)?;
+        let mut result = self.result_buffer(voxels, mode)?;
+        let samples = nx * ny * mode.samples_per_voxel();
+        let offset = self.data_offset()?;
+        let section_bytes = ny as u64 * self.row_bytes(mode)?;
+        for z in 0..layout.focal_planes {
+            let section = layout
+                .section_index(z, channel, timepoint)
+                .expect("section of checked channel and timepoint exists");
+            self.reader
+                .seek(SeekFrom::Start(offset + section as u64 * section_bytes))?;
+            self.read_into(mode, result.as_buffer(z * samples).segment(0, samples))?;
+        }
+        Ok(result)
+    }
+
+    /// Determines the byte order of the file and switches the reader to it.
+    ///
+    /// The machine stamp is authoritative, except for Priism files, which hold the origin in its
+    /// place and are recognized by `nDVID` in either byte order. Old files with a zeroed or garbage
+    /// stamp are guessed from the first four header words (`nx`, `ny`, `nz`, `mode`), which are
+    /// only plausible in the correct byte order.
+    fn detect_byte_order(&mut self) -> MrcResult<()> {
+        self.reader.seek(SeekFrom::Start(IVE_ID_OFFSET))?;
+        let mut ive_id = [0u8; 2];
+        self.reader.read_exact(&mut ive_id)?;
+        self.reader.seek(SeekFrom::Start(MACHINE_STAMP_OFFSET))?;
+        let mut stamp = [0u8; 4];
+        self.reader.read_exact(&mut stamp)?;
+
+        let byte_order = match ByteOrder::from_machine_stamp(stamp) {
+            _ if i16::from_le_bytes(ive_id) == IVE_ID => ByteOrder::LittleEndian,
+            _ if i16::from_be_bytes(ive_id) == IVE_ID => ByteOrder::BigEndian,
+            Some(byte_order) => byte_order,
+            None => {
+                self.reader.seek(SeekFrom::Start(0))?;
+                let mut words = [0u8; 16];
+                self.reader.read_exact(&mut words)?;
+                let little = is_plausible_start(&words, i32::from_le_bytes);
+                let big = is_plausible_start(&words, i32::from_be_bytes);
+                if big && !little {
+                    ByteOrder::BigEndian
+                } else {
+                    ByteOrder::LittleEndian
+                }
+            }
+        };
+
+        self.byte_order = byte_order;
+        self.reader.byte_order = byte_order;
+        Ok(())
+    }
+
     fn read_header(&mut self) -> MrcResult<()> {
-        // TODO: implement <01-10-20, kunzaatko> //
+        self.reader.seek(SeekFrom::Start(0))?;
+        let header = Header::read(&mut self.reader)?;
+        let (width, height) = header.image_dimensions();
+        self.width = width;
+        self.height = height;
+        self.header = Some(header);
         Ok(())
     }
 
-    /// Reads in the next image.
-    /// If there is no further image in the TIFF file a format error is returned.
-    /// To determine whether there are more images call `MrcDecoder::more_images` instead.
-    fn next_image(&mut self) -> MrcResult<()> {
-        // TODO: implement <01-10-20, kunzaatko> //
+    fn get_header(&self) -> MrcResult<&Header> {
+        self.header
+            .as_ref()
+            .ok_or_else(|| MrcFormatError::Format("header has not been read".to_string()).into())
+    }
+
+    /// Header of the volume as it is decoded, i.e. the canonical header if requested
+    fn volume_header(&self) -> MrcResult<&Header> {
+        if !self.canonical_axes {
+            return self.get_header();
+        }
+        match self.canonical_header {
+            Some(ref header) => Ok(header),
+            // The canonical header is missing only for an invalid axis mapping
+            None => {
+                Err(MrcFormatError::InvalidAxisMapping(self.get_header()?.axis_mapping()).into())
+            }
+        }
+    }
+
+    /// Axes of the columns, rows and sections if the volume has to be reordered when decoded
+    fn canonical_permutation(&self) -> MrcResult<Option<[usize; 3]>> {
+        if !self.canonical_axes {
+            return Ok(None);
+        }
+        let axes = self.get_header()?.axis_permutation()?;
+        Ok(Some(axes).filter(|&axes| axes != [0, 1, 2]))
+    }
+
+    /// Offset of the data block, which follows the main and the extended header
+    fn data_offset(&self) -> MrcResult<u64> {
+        Ok(HEADER_SIZE + self.get_header()?.extended_header_size() as u64)
+    }
+
+    /// Allocates a result able to hold `voxels` voxels of `mode`
+    fn result_buffer(&self, voxels: usize, mode: Mode) -> MrcResult<DecodingResult> {
+        match mode {
+            Mode::Mode0 if !self.get_header()?.signed_bytes() => {
+                DecodingResult::new_u8(voxels, &self.limits)
+            }
+            Mode::Mode0 => DecodingResult::new_i8(voxels, &self.limits),
+            Mode::Mode1 => DecodingResult::new_i16(voxels, &self.limits),
+            Mode::Mode2 => DecodingResult::new_f32(voxels, &self.limits),
+            Mode::Mode3 => DecodingResult::new_complex_i16(voxels, &self.limits),
+            Mode::Mode4 => DecodingResult::new_complex_f32(voxels, &self.limits),
+            Mode::Mode6 => DecodingResult::new_u16(voxels, &self.limits),
+            Mode::Mode12 if self.native_half => DecodingResult::new_f16(voxels, &self.limits),
+            Mode::Mode12 => DecodingResult::new_f32(voxels, &self.limits),
+            Mode::Mode101 => DecodingResult::new_u8(voxels, &self.limits),
+            Mode::Mode16 => {
+                let samples = voxels.checked_mul(3).ok_or(MrcError::LimitsExceeded)?;
+                DecodingResult::new_u8(samples, &self.limits)
+            }
+            _ => Err(MrcUnsupportedError::UnsupportedMode(mode).into()),
+        }
+    }
+
+    /// Number of bytes a row of `mode` occupies in the data block
+    fn row_bytes(&self, mode: Mode) -> MrcResult<u64> {
+        let [nx, _, _] = self.get_header()?.dimensions();
+        let nx = nx as u64;
+        Ok(match mode.bytes_per_voxel() {
+            Some(bytes) => nx * bytes as u64,
+            // Rows of 4 bit voxels are padded to a whole byte
+            None => nx.div_ceil(2),
+        })
+    }
+
+    /// Fills `buffer` with data of `mode` from the current position of the reader
+    ///
+    /// The data is read in segments of at most `Limits::intermediate_buffer_size` bytes.
+    fn read_into(&mut self, mode: Mode, mut buffer: DecodingBuffer) -> MrcResult<()> {
+        let [nx, _, _] = self.get_header()?.dimensions();
+        let intermediate = self.limits.intermediate_buffer_size;
+        let segment_len = match mode {
+            // Whole rows, as they are padded to a byte boundary
+            Mode::Mode101 => cmp::max(1, intermediate / cmp::max(1, nx.div_ceil(2))) * nx,
+            Mode::Mode12 => cmp::max(1, intermediate / 2),
+            _ => cmp::max(1, intermediate / buffer.byte_len()),
+        };
+
+        let len = buffer.len();
+        let mut start = 0;
+        while start < len {
+            let end = cmp::min(start + segment_len, len);
+            self.read_segment(mode, buffer.segment(start, end))?;
+            start = end;
+        }
+        Ok(())
+    }
+
+    /// Fills `buffer` with data of `mode` from the current position of the reader, swapping the
+    /// bytes if needed
+    fn read_segment(&mut self, mode: Mode, buffer: DecodingBuffer) -> MrcResult<()> {
+        match buffer {
+            DecodingBuffer::F32(buf) if mode == Mode::Mode12 => self.reader.read_f16_into(buf)?,
+            DecodingBuffer::U8(buf) if mode == Mode::Mode101 => {
+                let [nx, _, _] = self.get_header()?.dimensions();
+                self.reader.read_u4_into(buf, nx)?
+            }
+            DecodingBuffer::U8(buf) => self.reader.read_exact(buf)?,
+            DecodingBuffer::U16(buf) => self.reader.read_u16_into(buf)?,
+            DecodingBuffer::U32(buf) => self.reader.read_u32_into(buf)?,
+            DecodingBuffer::U64(buf) => self.reader.read_u64_into(buf)?,
+            DecodingBuffer::F32(buf) => self.reader.read_f32_into(buf)?,
+            DecodingBuffer::F64(buf) => self.reader.read_f64_into(buf)?,
+            DecodingBuffer::F16(buf) => self.reader.read_u16_into(buf)?,
+            DecodingBuffer::I8(buf) => self.reader.read_i8_into(buf)?,
+            DecodingBuffer::I16(buf) | DecodingBuffer::ComplexI16(buf) => {
+                self.reader.read_i16_into(buf)?
+            }
+            DecodingBuffer::I32(buf) => self.reader.read_i32_into(buf)?,
+            DecodingBuffer::ComplexF32(buf) => self.reader.read_f32_into(buf)?,
+        }
+        Ok(())
+    }
+
+    /// Decodes the whole data block
+    ///
+    /// The voxels are ordered with X changing fastest and Z slowest. RGB voxels (`Mode16`) are
+    /// returned as three consecutive samples, complex voxels (`Mode3`, `Mode4`) as their real and
+    /// imaginary parts. Packed 4 bit voxels (`Mode101`) are unpacked to one byte each.
+    pub fn read_volume(&mut self) -> MrcResult<DecodingResult> {
+        let header = self.get_header()?;
+        let mode = header.data_mode()?;
+        let [nx, ny, nz] = header.dimensions();
+        let voxels = nx
+            .checked_mul(ny)
+            .and_then(|n| n.checked_mul(nz))
+            .ok_or(MrcError::LimitsExceeded)?;
+
+        if let Some(axes) = self.canonical_permutation()? {
+            let [x, y, z] = self.volume_header()?.dimensions();
+            let mut result = self.result_buffer(voxels, mode)?;
+            self.read_canonical_region_into(axes, [0..x, 0..y, 0..z], result.as_buffer(0))?;
+            return Ok(result);
+        }
+
+        let mut result = self.result_buffer(voxels, mode)?;
+        let offset = self.data_offset()?;
+        self.reader.seek(SeekFrom::Start(offset))?;
+        self.read_into(mode, result.as_buffer(0))?;
+        Ok(result)
+    }
+
+    /// Decodes section `z` of the volume
+    ///
+    /// Only the section is read from the file. The layout of the result is the one of
+    /// `read_volume` for a volume of a single section.
+    pub fn read_section(&mut self, z: usize) -> MrcResult<DecodingResult> {
+        let [nx, ny, nz] = self.volume_header()?.dimensions();
+        if z >= nz {
+            return Err(MrcUsageError::SectionOutOfBounds {
+                section: z,
+                sections: nz,
+            }
+            .into());
+        }
+        self.read_region(0..nx, 0..ny, z..z + 1)
+    }
+
+    /// Decodes the sub-volume spanning the columns `x`, the rows `y` and the sections `z`
+    ///
+    /// Only the needed rows are read from the file. The layout of the result is the one of
+    /// `read_volume` for a volume of the size of the region.
+    pub fn read_region(
+        &mut self,
+        x: Range<usize>,
+        y: Range<usize>,
+        z: Range<usize>,
+    ) -> MrcResult<DecodingResult> {
+        let axes = match self.canonical_permutation()? {
+            Some(axes) => axes,
+            None => return self.read_file_region(x, y, z),
+        };
+        let header = self.volume_header()?;
+        let mode = header.data_mode()?;
+        let [nx, ny, nz] = header.dimensions();
+        check_range('x', &x, nx)?;
+        check_range('y', &y, ny)?;
+        check_range('z', &z, nz)?;
+
+        let voxels = x
+            .len()
+            .checked_mul(y.len())
+            .and_then(|n| n.checked_mul(z.len()))
+            .ok_or(MrcError::LimitsExceeded)?;
+        let mut result = self.result_buffer(voxels, mode)?;
+        self.read_canonical_region_into(axes, [x, y, z], result.as_buffer(0))?;
+        Ok(result)
+    }
+
+    /// Decodes the region spanning `ranges` along X, Y and Z into `buffer` ordered with X changing
+    /// fastest, for a file with columns, rows and sections along `axes`
+    fn read_canonical_region_into(
+        &mut self,
+        axes: [usize; 3],
+        ranges: [Range<usize>; 3],
+        buffer: DecodingBuffer,
+    ) -> MrcResult<()> {
+        let [columns, rows, sections] = axes.map(|axis| ranges[axis].clone());
+        let dimensions = [columns.len(), rows.len(), sections.len()];
+        let samples = self.get_header()?.data_mode()?.samples_per_voxel();
+        let region = self.read_file_region(columns, rows, sections)?;
+        reorder_into(&region, buffer, dimensions, axes, samples)
+    }
+
+    /// Decodes the region spanning the columns `x`, the rows `y` and the sections `z` of the file
+    fn read_file_region(
+        &mut self,
+        x: Range<usize>,
+        y: Range<usize>,
+        z: Range<usize>,
+    ) -> MrcResult<DecodingResult> {
+        let header = self.get_header()?;
+        let mode = header.data_mode()?;
+        let [nx, ny, nz] = header.dimensions();
+        check_range('x', &x, nx)?;
+        check_range('y', &y, ny)?;
+        check_range('z', &z, nz)?;
+
+        let (width, height) = (x.len(), y.len());
+        let voxels = width
+            .checked_mul(height)
+            .and_then(|n| n.checked_mul(z.len()))
+            .ok_or(MrcError::LimitsExceeded)?;
+        let mut result = self.result_buffer(voxels, mode)?;
+
+        let data_offset = self.data_offset()?;
+        let row_bytes = self.row_bytes(mode)?;
+        let column_offset = match mode.bytes_per_voxel() {
+            Some(bytes) => (x.start * bytes) as u64,
+            None => (x.start / 2) as u64,
+        };
+        // Whole rows are contiguous in the file, so they are read together
+        let rows_per_read = if width == nx { height } else { 1 };
+        let samples = rows_per_read * width * mode.samples_per_voxel();
+        // A packed row starting at an odd column shares its first byte with the previous voxel
+        let mut unaligned = if mode == Mode::Mode101 && x.start % 2 == 1 {
+            vec![0u8; width + 1]
+        } else {
+            Vec::new()
+        };
+
+        let mut index = 0;
+        for z in z {
+            for y in y.clone().step_by(cmp::max(1, rows_per_read)) {
+                let row = (z * ny + y) as u64;
+                self.reader.seek(SeekFrom::Start(
+                    data_offset + row * row_bytes + column_offset,
+                ))?;
+                match result {
+                    DecodingResult::U8(ref mut buf) if !unaligned.is_empty() => {
+                        self.reader.read_u4_into(&mut unaligned, width + 1)?;
+                        buf[index..index + width].copy_from_slice(&unaligned[1..]);
+                    }
+                    _ => self.read_into(mode, result.as_buffer(index).segment(0, samples))?,
+                }
+                index += samples;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Number of sections (`nz`) in the data block
+    pub fn section_count(&self) -> MrcResult<usize> {
+        Ok(self.volume_header()?.dimensions()[2])
+    }
+
+    /// Whether there are sections left to be decoded by `read_next_section`
+    pub fn more_sections(&self) -> bool {
+        match (&self.section_decoder, self.section_count()) {
+            (Some(state), Ok(count)) => state.section_index < count,
+            _ => false,
+        }
+    }
+
+    /// Checks that `buffer` is of the type `read_volume` returns and large enough to hold a
+    /// section, returning the number of elements a section fills
+    fn section_samples(&self, buffer: &DecodingBuffer) -> MrcResult<usize> {
+        let header = self.volume_header()?;
+        let mode = header.data_mode()?;
+        let [nx, ny, _] = header.dimensions();
+        if !buffer.fits(mode) {
+            return Err(MrcUnsupportedError::UnsupportedDataType.into());
+        }
+        let samples = nx
+            .checked_mul(ny)
+            .and_then(|n| n.checked_mul(mode.samples_per_voxel()))
+            .ok_or(MrcError::LimitsExceeded)?;
+        if buffer.len() < samples {
+            return Err(MrcUsageError::DataSizeMismatch {
+                expected: samples,
+                actual: buffer.len(),
+            }
+            .into());
+        }
+        Ok(samples)
+    }
+
+    /// Decodes section `z` into the first `samples` elements of `buffer`, as checked by
+    /// `section_samples`
+    fn read_section_into_at(
+        &mut self,
+        z: usize,
+        samples: usize,
+        mut buffer: DecodingBuffer,
+    ) -> MrcResult<()> {
+        if let Some(axes) = self.canonical_permutation()? {
+            let [nx, ny, _] = self.volume_header()?.dimensions();
+            let ranges = [0..nx, 0..ny, z..z + 1];
+            return self.read_canonical_region_into(axes, ranges, buffer.segment(0, samples));
+        }
+        let header = self.get_header()?;
+        let mode = header.data_mode()?;
+        let ny = header.dimensions()[1];
+        let offset = self.data_offset()? + z as u64 * ny as u64 * self.row_bytes(mode)?;
+        self.reader.seek(SeekFrom::Start(offset))?;
+        self.read_into(mode, buffer.segment(0, samples))
+    }
+
+    /// Decodes the next section into a newly allocated result
+    ///
+    /// The layout of the result is the one of `read_volume` for a volume of a single section. If
+    /// there is no further section, a usage error is returned.
+    pub fn read_next_section(&mut self) -> MrcResult<DecodingResult> {
+        let header = self.volume_header()?;
+        let mode = header.data_mode()?;
+        let [nx, ny, _] = header.dimensions();
+        let voxels = nx.checked_mul(ny).ok_or(MrcError::LimitsExceeded)?;
+        let mut result = self.result_buffer(voxels, mode)?;
+        self.read_next_section_into(result.as_buffer(0))?;
+        Ok(result)
+    }
+
+    /// Decodes the next section into `buffer`
+    ///
+    /// This allows to reuse a single buffer for all the sections of a volume. The buffer has to be
+    /// of the type `read_volume` returns for the file and at least `nx * ny` voxels long. If there
+    /// is no further section, a usage error is returned.
+    pub fn read_next_section_into(&mut self, buffer: DecodingBuffer) -> MrcResult<()> {
+        if !self.more_sections() {
+            return Err(MrcUsageError::NoMoreSections.into());
+        }
+        let samples = self.section_samples(&buffer)?;
+        let z = match self.section_decoder {
+            Some(ref state) => state.section_index,
+            None => return Err(MrcUsageError::NoMoreSections.into()),
+        };
+        // A failed read leaves the section to be decoded again
+        self.read_section_into_at(z, samples, buffer)?;
+        if let Some(ref mut state) = self.section_decoder {
+            state.section_index += 1;
+        }
+        Ok(())
+    }
+
+    /// Iterates over the remaining sections, decoding one at a time
+    pub fn sections(&mut self) -> Sections<'_, R> {
+        Sections { decoder: self }
+    }
+
+    /// Starts section decoding at the first section
+    fn init_sections(&mut self) -> MrcResult<()> {
+        self.section_decoder = Some(SectionDecodeState { section_index: 0 });
         Ok(())
     }
 
     /// Initializes the decoder.
     pub fn init(mut self) -> MrcResult<Decoder<R>> {
+        self.detect_byte_order()?;
         self.read_header()?;
-        self.next_image()?;
+        self.init_sections()?;
         Ok(self)
     }
 }
+
+/// Iterator over the sections of a volume, see `Decoder::sections`
+pub struct Sections<'a, R>
+where
+    R: Read + Seek,
+{
+    decoder: &'a mut Decoder<R>,
+}
+
+impl<'a, R: Read + Seek> Iterator for Sections<'a, R> {
+    type Item = MrcResult<DecodingResult>;
+
+    fn next(&mut self) -> Option<MrcResult<DecodingResult>> {
+        if self.decoder.more_sections() {
+            Some(self.decoder.read_next_section())
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether the first four header words (`nx`, `ny`, `nz`, `mode`) look sane when decoded with
+/// `from_bytes`.
+///
+/// A small value in the wrong byte order turns into a huge (or negative) one, so dimensions are
+/// limited to 2^24 and the mode to the range of codes in use.
+fn is_plausible_start(words: &[u8; 16], from_bytes: fn([u8; 4]) -> i32) -> bool {
+    const MAX_DIMENSION: i32 = 1 << 24;
+    let word = |i: usize| {
+        from_bytes([
+            words[4 * i],
+            words[4 * i + 1],
+            words[4 * i + 2],
+            words[4 * i + 3],
+        ])
+    };
+    let dimensions_plausible = (0..3).all(|i| (0..MAX_DIMENSION).contains(&word(i)));
+    let mode = word(3);
+    dimensions_plausible && ((0..=16).contains(&mode) || mode == 101)
+}
+
+/// Checks that `range` along `axis` lies within a volume of `size` voxels
+fn check_range(axis: char, range: &Range<usize>, size: usize) -> MrcResult<()> {
+    if range.start > range.end || range.end > size {
+        return Err(MrcUsageError::RegionOutOfBounds {
+            axis,
+            start: range.start,
+            end: range.end,
+            size,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Copies `source`, a region of `dimensions` along the columns, rows and sections of a file, into
+/// `target` ordered with X changing fastest, where `axes` are the axes of the columns, rows and
+/// sections
+fn reorder_into(
+    source: &DecodingResult,
+    target: DecodingBuffer,
+    dimensions: [usize; 3],
+    axes: [usize; 3],
+    samples: usize,
+) -> MrcResult<()> {
+    macro_rules! reorder {
+        ($($variant:ident),*) => {
+            match (source, target) {
+                $((DecodingResult::$variant(source), DecodingBuffer::$variant(target)) => {
+                    reorder_samples(source, target, dimensions, axes, samples)
+                })*
+                _ => return Err(MrcUnsupportedError::UnsupportedDataType.into()),
+            }
+        };
+    }
+    reorder!(U8, U16, U32, U64, F32, F64, F16, I8, I16, I32, ComplexI16, ComplexF32);
+    Ok(())
+}
+
+fn reorder_samples<T: Copy>(
+    source: &[T],
+    target: &mut [T],
+    dimensions: [usize; 3],
+    axes: [usize; 3],
+    samples: usize,
+) {
+    let mut reordered = [0; 3];
+    for (&size, &axis) in dimensions.iter().zip(axes.iter()) {
+        reordered[axis] = size;
+    }
+    let strides = [
+        samples,
+        samples * reordered[0],
+        samples * reordered[0] * reordered[1],
+    ];
+    // Steps in `target` along the columns, rows and sections of `source`
+    let [column, row, section] = axes.map(|axis| strides[axis]);
+
+    let mut voxels = source.chunks_exact(samples);
+    for s in 0..dimensions[2] {
+        for r in 0..dimensions[1] {
+            let start = s * section + r * row;
+            for c in 0..dimensions[0] {
+                let at = start + c * column;
+                if let Some(voxel) = voxels.next() {
+                    target[at..at + samples].copy_from_slice(voxel);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::{Encoder, EncodingBuffer};
+    use std::io::Cursor;
+
+    const BYTE_ORDERS: [ByteOrder; 2] = [ByteOrder::LittleEndian, ByteOrder::BigEndian];
+
+    fn encode(
+        byte_order: ByteOrder,
+        mode: Mode,
+        dimensions: [usize; 3],
+        data: EncodingBuffer,
+    ) -> Decoder<Cursor<Vec<u8>>> {
+        let mut encoder = Encoder::new(Cursor::new(Vec::new())).with_byte_order(byte_order);
+        encoder.write_volume(mode, dimensions, data).unwrap();
+        Decoder::new(Cursor::new(encoder.into_inner().into_inner())).unwrap()
+    }
+
+    #[test]
+    fn round_trip_in_both_byte_orders() {
+        let words: Vec<i16> = (0..24).map(|n| n * 1000 - 12000).collect();
+        let floats: Vec<f32> = (0..24).map(|n| n as f32 * 0.25 - 3.0).collect();
+        let unsigned: Vec<u16> = (0..24).map(|n| n * 2500).collect();
+        for &byte_order in &BYTE_ORDERS {
+            let mut decoder = encode(
+                byte_order,
+                Mode::Mode1,
+                [4, 3, 2],
+                EncodingBuffer::I16(&words),
+            );
+            assert_eq!(decoder.byte_order(), byte_order);
+            match decoder.read_volume().unwrap() {
+                DecodingResult::I16(data) => assert_eq!(data, words),
+                result => panic!("unexpected {:?}", result),
+            }
+
+            let mut decoder = encode(
+                byte_order,
+                Mode::Mode2,
+                [4, 3, 2],
+                EncodingBuffer::F32(&floats),
+            );
+            match decoder.read_volume().unwrap() {
+                DecodingResult::F32(data) => assert_eq!(data, floats),
+                result => panic!("unexpected {:?}", result),
+            }
+
+            let mut decoder = encode(
+                byte_order,
+                Mode::Mode4,
+                [4, 3, 1],
+                EncodingBuffer::ComplexF32(&floats),
+            );
+            match decoder.read_volume().unwrap() {
+                DecodingResult::ComplexF32(data) => assert_eq!(data, floats),
+                result => panic!("unexpected {:?}", result),
+            }
+
+            let mut decoder = encode(
+                byte_order,
+                Mode::Mode6,
+                [4, 3, 2],
+                EncodingBuffer::U16(&unsigned),
+            );
+            match decoder.read_volume().unwrap() {
+                DecodingResult::U16(data) => assert_eq!(data, unsigned),
+                result => panic!("unexpected {:?}", result),
+            }
+
+            // Quarters are exact in half precision
+            let mut decoder = encode(
+                byte_order,
+                Mode::Mode12,
+                [4, 3, 2],
+                EncodingBuffer::F32(&floats),
+            );
+            match decoder.read_volume().unwrap() {
+                DecodingResult::F32(data) => assert_eq!(data, floats),
+                result => panic!("unexpected {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn packed_regions_of_odd_width_and_start() {
+        let [nx, ny, nz] = [5, 3, 2];
+        let voxel = |x: usize, y: usize, z: usize| ((x + 3 * y + 7 * z) % 16) as u8;
+        let data: Vec<u8> = (0..nz)
+            .flat_map(|z| (0..ny).flat_map(move |y| (0..nx).map(move |x| voxel(x, y, z))))
+            .collect();
+        for &byte_order in &BYTE_ORDERS {
+            let mut decoder = encode(
+                byte_order,
+                Mode::Mode101,
+                [nx, ny, nz],
+                EncodingBuffer::U8(&data),
+            );
+            match decoder.read_volume().unwrap() {
+                DecodingResult::U8(volume) => assert_eq!(volume, data),
+                result => panic!("unexpected {:?}", result),
+            }
+
+            for x in [0..5, 1..4, 1..2, 3..5, 4..5, 2..2] {
+                let (y, z) = (1..3, 1..2);
+                let expected: Vec<u8> = z
+                    .clone()
+                    .flat_map(|z| {
+                        let x = x.clone();
+                        y.clone()
+                            .flat_map(move |y| x.clone().map(move |x| voxel(x, y, z)))
+                    })
+                    .collect();
+                match decoder.read_region(x.clone(), y, z).unwrap() {
+                    DecodingResult::U8(region) => assert_eq!(region, expected, "{:?}", x),
+                    result => panic!("unexpected {:?}", result),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn statistics_of_complex_data() {
+        let mut encoder = Encoder::new(Cursor::new(Vec::new()));
+        let mut volume = encoder.new_volume(Mode::Mode4, [2, 1, 1]).unwrap();
+        assert!(matches!(
+            volume.write_data(EncodingBuffer::ComplexF32(&[3.0])),
+            Err(MrcError::UsageError(MrcUsageError::PartialVoxel))
+        ));
+        volume
+            .write_data(EncodingBuffer::ComplexF32(&[3.0, 4.0]))
+            .unwrap();
+        volume
+            .write_data(EncodingBuffer::ComplexF32(&[6.0, 8.0]))
+            .unwrap();
+        volume.finish().unwrap();
+
+        let decoder = Decoder::new(Cursor::new(encoder.into_inner().into_inner())).unwrap();
+        let statistics = decoder.header().unwrap().density_statistics();
+        assert_eq!(statistics.range(), Some((5.0, 10.0)));
+        assert_eq!(statistics.mean(), Some(7.5));
+        assert_eq!(statistics.rms(), Some(2.5));
+
+        let decoder = encode(
+            ByteOrder::BigEndian,
+            Mode::Mode3,
+            [2, 1, 1],
+            EncodingBuffer::ComplexI16(&[-3, 4, 0, -1]),
+        );
+        let statistics = decoder.header().unwrap().density_statistics();
+        assert_eq!(statistics.range(), Some((1.0, 5.0)));
+        assert_eq!(statistics.mean(), Some(3.0));
+    }
+
+    #[test]
+    fn rejected_writes_do_not_count_towards_statistics() {
+        let mut encoder = Encoder::new(Cursor::new(Vec::new()));
+        let mut volume = encoder.new_volume(Mode::Mode101, [3, 1, 1]).unwrap();
+        assert!(matches!(
+            volume.write_data(EncodingBuffer::U8(&[15, 15])),
+            Err(MrcError::UsageError(MrcUsageError::PartialRow))
+        ));
+        assert!(matches!(
+            volume.write_data(EncodingBuffer::U8(&[0, 15, 15, 15, 15, 15])),
+            Err(MrcError::UsageError(MrcUsageError::DataSizeMismatch { .. }))
+        ));
+        volume.write_data(EncodingBuffer::U8(&[1, 2, 3])).unwrap();
+        volume.finish().unwrap();
+
+        let decoder = Decoder::new(Cursor::new(encoder.into_inner().into_inner())).unwrap();
+        let statistics = decoder.header().unwrap().density_statistics();
+        assert_eq!(statistics.range(), Some((1.0, 3.0)));
+        assert_eq!(statistics.mean(), Some(2.0));
+    }
+    #[test]
+    fn failed_section_reads_are_retried() {
+        let data: Vec<i16> = (0..12).collect();
+        let mut encoder = Encoder::new(Cursor::new(Vec::new()));
+        encoder
+            .write_volume(Mode::Mode1, [3, 2, 2], EncodingBuffer::I16(&data))
+            .unwrap();
+        let mut bytes = encoder.into_inner().into_inner();
+        let full = bytes.clone();
+        bytes.truncate(bytes.len() - 2);
+
+        let mut decoder = Decoder::new(Cursor::new(bytes)).unwrap();
+        let mut section = [0i16; 6];
+        decoder
+            .read_next_section_into(DecodingBuffer::I16(&mut section))
+            .unwrap();
+        assert_eq!(section, [0, 1, 2, 3, 4, 5]);
+        for _ in 0..2 {
+            assert!(matches!(
+                decoder.read_next_section_into(DecodingBuffer::I16(&mut section)),
+                Err(MrcError::IoError(_))
+            ));
+            assert!(decoder.more_sections());
+        }
+
+        // The failed section is the next one once the data is complete
+        decoder.reader = SmartReader::wrap(Cursor::new(full), decoder.byte_order);
+        decoder
+            .read_next_section_into(DecodingBuffer::I16(&mut section))
+            .unwrap();
+        assert_eq!(section, [6, 7, 8, 9, 10, 11]);
+        assert!(!decoder.more_sections());
+    }
+
+    #[test]
+    fn sections_into_a_reused_buffer() {
+        let data: Vec<f32> = (0..12).map(|n| n as f32).collect();
+        for &byte_order in &BYTE_ORDERS {
+            let mut decoder = encode(
+                byte_order,
+                Mode::Mode2,
+                [3, 2, 2],
+                EncodingBuffer::F32(&data),
+            );
+            let mut section = [0.0f32; 6];
+            let mut buffer = DecodingBuffer::F32(&mut section);
+            let mut decoded = Vec::new();
+            while decoder.more_sections() {
+                decoder.read_next_section_into(buffer.copy()).unwrap();
+                if let DecodingBuffer::F32(ref values) = buffer {
+                    decoded.extend_from_slice(values);
+                }
+            }
+            assert_eq!(decoded, data);
+        }
+    }
+
+    #[test]
+    fn priism_layout_requires_the_priism_identifier() {
+        let mut encoder = Encoder::new(Cursor::new(Vec::new()))
+            .with_extended_header(Some(ExtendedHeaderType::AGAR), vec![0; 64]);
+        encoder
+            .write_volume(Mode::Mode1, [2, 2, 2], EncodingBuffer::I16(&[0; 8]))
+            .unwrap();
+        let mut decoder = Decoder::new(Cursor::new(encoder.into_inner().into_inner())).unwrap();
+        for result in [
+            decoder.ive_header().err(),
+            decoder.read_ive_stack(0, 0).err(),
+        ] {
+            assert!(matches!(
+                result,
+                Some(MrcError::UsageError(MrcUsageError::SoftwareMismatch {
+                    expected: Mode::IVE,
+                    found: None,
+                }))
+            ));
+        }
+        assert!(decoder.ive_sections().is_ok());
+    }
+    #[test]
+    fn priism_stacks_of_each_image_sequence() {
+        // 4 focal planes of 2 channels at 3 timepoints, each section filled with its index
+        let data: Vec<i16> = (0..24).flat_map(|section| vec![section; 2]).collect();
+        for sequence in 0..3i16 {
+            let mut encoder =
+                Encoder::new(Cursor::new(Vec::new())).with_byte_order(ByteOrder::LittleEndian);
+            encoder
+                .write_volume(Mode::Mode1, [2, 1, 24], EncodingBuffer::I16(&data))
+                .unwrap();
+            let mut bytes = encoder.into_inner().into_inner();
+            for &(offset, value) in &[(96, IVE_ID), (180, 3), (182, sequence), (196, 2)] {
+                bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+            }
+
+            let mut decoder = Decoder::new(Cursor::new(bytes)).unwrap();
+            let layout = decoder.ive_header().unwrap();
+            assert_eq!(layout.focal_planes, 4);
+            for channel in 0..2 {
+                for timepoint in 0..3 {
+                    let expected: Vec<i16> = (0..4)
+                        .map(|z| layout.section_index(z, channel, timepoint).unwrap() as i16)
+                        .flat_map(|section| vec![section; 2])
+                        .collect();
+                    match decoder.read_ive_stack(channel, timepoint).unwrap() {
+                        DecodingResult::I16(stack) => assert_eq!(stack, expected),
+                        result => panic!("unexpected {:?}", result),
+                    }
+                }
+            }
+            assert!(decoder.read_ive_stack(2, 0).is_err());
+            assert!(decoder.read_ive_stack(0, 3).is_err());
+        }
+    }
+    /// Encodes `data` with the axis mapping (`mapc`, `mapr`, `maps`) set to `mapping`
+    fn encode_mapped(
+        mapping: [i32; 3],
+        dimensions: [usize; 3],
+        data: &[i16],
+    ) -> Decoder<Cursor<Vec<u8>>> {
+        let mut encoder =
+            Encoder::new(Cursor::new(Vec::new())).with_byte_order(ByteOrder::LittleEndian);
+        encoder
+            .write_volume(Mode::Mode1, dimensions, EncodingBuffer::I16(data))
+            .unwrap();
+        let mut bytes = encoder.into_inner().into_inner();
+        for (i, &axis) in mapping.iter().enumerate() {
+            bytes[64 + 4 * i..68 + 4 * i].copy_from_slice(&axis.to_le_bytes());
+        }
+        Decoder::new(Cursor::new(bytes)).unwrap()
+    }
+
+    #[test]
+    fn canonical_axes_reorder_the_volume() {
+        // Columns along Z, rows along X and sections along Y
+        let data: Vec<i16> = (0..24).collect();
+        let decoder = encode_mapped([3, 1, 2], [2, 3, 4], &data);
+        assert_eq!(decoder.header().unwrap().dimensions(), [2, 3, 4]);
+
+        let mut decoder = decoder.with_canonical_axes(true);
+        assert_eq!(decoder.header().unwrap().dimensions(), [3, 4, 2]);
+        assert_eq!(decoder.header().unwrap().axis_mapping(), [1, 2, 3]);
+        assert_eq!(decoder.dimensions().unwrap(), (3, 4));
+        let mut expected = vec![0; 24];
+        for z in 0..2 {
+            for y in 0..4 {
+                for x in 0..3 {
+                    // Column z, row x, section y of the file
+                    expected[x + 3 * y + 12 * z] = (z + 2 * x + 6 * y) as i16;
+                }
+            }
+        }
+        match decoder.read_volume().unwrap() {
+            DecodingResult::I16(volume) => assert_eq!(volume, expected),
+            result => panic!("unexpected {:?}", result),
+        }
+        match decoder.read_section(1).unwrap() {
+            DecodingResult::I16(section) => assert_eq!(section, expected[12..]),
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn canonical_axes_of_an_invalid_mapping() {
+        let data: Vec<i16> = (0..24).collect();
+        let decoder = encode_mapped([1, 1, 3], [2, 3, 4], &data);
+        assert!(decoder.header().is_ok());
+
+        let mut decoder = decoder.with_canonical_axes(true);
+        assert!(matches!(
+            decoder.header(),
+            Err(MrcError::FormatError(MrcFormatError::InvalidAxisMapping([
+                1, 1, 3
+            ])))
+        ));
+        assert!(decoder.read_volume().is_err());
+    }
+    /// Encodes a volume of 2 sections with SerialEM records of 4 bytes holding the tilt angle and
+    /// the magnification, stamped as written by IMOD if `imod`
+    fn encode_serialem(
+        ext_type: Option<ExtendedHeaderType>,
+        imod: bool,
+    ) -> Decoder<Cursor<Vec<u8>>> {
+        let mut extended = Vec::new();
+        for &word in &[1500i16, 250, -750, 300] {
+            extended.extend_from_slice(&word.to_le_bytes());
+        }
+        let mut encoder = Encoder::new(Cursor::new(Vec::new()))
+            .with_byte_order(ByteOrder::LittleEndian)
+            .with_extended_header(ext_type, extended);
+        encoder
+            .write_volume(Mode::Mode1, [2, 1, 2], EncodingBuffer::I16(&[0; 4]))
+            .unwrap();
+        let mut bytes = encoder.into_inner().into_inner();
+        bytes[128..130].copy_from_slice(&4i16.to_le_bytes());
+        bytes[130..132].copy_from_slice(&9i16.to_le_bytes());
+        if imod {
+            bytes[152..156].copy_from_slice(&header::IMOD_STAMP.to_le_bytes());
+        }
+        Decoder::new(Cursor::new(bytes)).unwrap()
+    }
+
+    #[test]
+    fn serialem_records_of_untyped_imod_files() {
+        for (ext_type, imod) in [(Some(ExtendedHeaderType::SERI), false), (None, true)] {
+            let records = encode_serialem(ext_type, imod).serialem_records().unwrap();
+            let angles: Vec<_> = records.iter().map(|record| record.tilt_angle).collect();
+            assert_eq!(angles, [Some(15.0), Some(-7.5)]);
+            let magnifications: Vec<_> = records.iter().map(|r| r.magnification).collect();
+            assert_eq!(magnifications, [Some(25000), Some(30000)]);
+        }
+
+        // An untyped extended header is not taken for records unless written by IMOD
+        assert!(matches!(
+            encode_serialem(None, false).serialem_records(),
+            Err(MrcError::UsageError(
+                MrcUsageError::ExtendedHeaderMismatch { found: None, .. }
+            ))
+        ));
+    }
+    #[test]
+    fn symmetry_operators_round_trip() {
+        // P 21 21 21
+        let operators: Vec<SymmetryOperator> = [
+            "X,Y,Z",
+            "-X+1/2,-Y,Z+1/2",
+            "X+1/2,-Y+1/2,-Z",
+            "-X,Y+1/2,-Z+1/2",
+        ]
+        .iter()
+        .map(|text| text.parse().unwrap())
+        .collect();
+        for &byte_order in &BYTE_ORDERS {
+            let mut encoder = Encoder::new(Cursor::new(Vec::new()))
+                .with_byte_order(byte_order)
+                .with_space_group(19)
+                .with_symmetry_operators(&operators);
+            encoder
+                .write_volume(Mode::Mode2, [2, 2, 2], EncodingBuffer::F32(&[0.0; 8]))
+                .unwrap();
+            let mut decoder = Decoder::new(Cursor::new(encoder.into_inner().into_inner())).unwrap();
+            let header = decoder.header().unwrap();
+            assert_eq!(header.space_group(), 19);
+            assert_eq!(
+                header.extended_header_type(),
+                Some(ExtendedHeaderType::CCP4)
+            );
+            assert_eq!(header.extended_header_size(), 4 * ccp4::RECORD_SIZE);
+            assert_eq!(decoder.symmetry_operators().unwrap(), operators);
+        }
+    }
+    /// Bytes of a 3 x 2 x 1 `Mode1` volume with the machine stamp replaced by `stamp`
+    fn with_machine_stamp(byte_order: ByteOrder, stamp: [u8; 4]) -> Vec<u8> {
+        let mut encoder = Encoder::new(Cursor::new(Vec::new())).with_byte_order(byte_order);
+        encoder
+            .write_volume(
+                Mode::Mode1,
+                [3, 2, 1],
+                EncodingBuffer::I16(&[1, 2, 3, 4, 5, 6]),
+            )
+            .unwrap();
+        let mut bytes = encoder.into_inner().into_inner();
+        let offset = MACHINE_STAMP_OFFSET as usize;
+        bytes[offset..offset + 4].copy_from_slice(&stamp);
+        bytes
+    }
+
+    #[test]
+    fn byte_order_of_machine_stamps() {
+        for &(stamp, byte_order) in &[
+            ([0x44, 0x44, 0x00, 0x00], ByteOrder::LittleEndian),
+            ([0x44, 0x41, 0x00, 0x00], ByteOrder::LittleEndian),
+            ([0x11, 0x11, 0x00, 0x00], ByteOrder::BigEndian),
+        ] {
+            assert_eq!(ByteOrder::from_machine_stamp(stamp), Some(byte_order));
+            let bytes = with_machine_stamp(byte_order, stamp);
+            let mut decoder = Decoder::new(Cursor::new(bytes)).unwrap();
+            assert_eq!(decoder.byte_order(), byte_order);
+            assert_eq!(decoder.header().unwrap().dimensions(), [3, 2, 1]);
+            match decoder.read_volume().unwrap() {
+                DecodingResult::I16(data) => assert_eq!(data, [1, 2, 3, 4, 5, 6]),
+                result => panic!("unexpected {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn byte_order_of_zeroed_machine_stamps() {
+        for &stamp in &[[0; 4], [0x20, 0x20, 0x20, 0x20]] {
+            assert_eq!(ByteOrder::from_machine_stamp(stamp), None);
+            for &byte_order in &BYTE_ORDERS {
+                let bytes = with_machine_stamp(byte_order, stamp);
+                let decoder = Decoder::new(Cursor::new(bytes)).unwrap();
+                assert_eq!(decoder.byte_order(), byte_order);
+                assert_eq!(decoder.header().unwrap().dimensions(), [3, 2, 1]);
+            }
+        }
+    }
+}
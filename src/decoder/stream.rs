@@ -4,7 +4,7 @@ use crate::bytecast;
 use std::io::{self, Read, Seek};
 
 /// Byte order of the MRC file.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ByteOrder {
     /// little endian byte order
     LittleEndian,
@@ -12,6 +12,37 @@ pub enum ByteOrder {
     BigEndian,
 }
 
+impl ByteOrder {
+    /// Byte order declared by the machine stamp (`MACHST`, bytes 213-216)
+    ///
+    /// Only the first two bytes are significant. Returns `None` for a zeroed or unknown stamp, as
+    /// written by some older software, in which case the byte order has to be guessed.
+    pub fn from_machine_stamp(stamp: [u8; 4]) -> Option<ByteOrder> {
+        match [stamp[0], stamp[1]] {
+            [0x44, 0x44] | [0x44, 0x41] => Some(ByteOrder::LittleEndian),
+            [0x11, 0x11] => Some(ByteOrder::BigEndian),
+            _ => None,
+        }
+    }
+
+    /// Byte order of the host
+    pub fn native() -> ByteOrder {
+        if cfg!(target_endian = "big") {
+            ByteOrder::BigEndian
+        } else {
+            ByteOrder::LittleEndian
+        }
+    }
+
+    /// Machine stamp that declares this byte order
+    pub fn machine_stamp(self) -> [u8; 4] {
+        match self {
+            ByteOrder::LittleEndian => [0x44, 0x44, 0x00, 0x00],
+            ByteOrder::BigEndian => [0x11, 0x11, 0x00, 0x00],
+        }
+    }
+}
+
 /// Reader that is aware of the byte order.
 pub trait EndianReader: Read {
     /// Byte order that should be adhered to
@@ -46,6 +77,34 @@ pub trait EndianReader: Read {
         Ok(())
     }
 
+    #[inline(always)]
+    fn read_i8_into(&mut self, buffer: &mut [i8]) -> Result<(), io::Error> {
+        self.read_exact(bytecast::i8_as_ne_mut_bytes(buffer))
+    }
+
+    /// Reads 4 bit unsigned ints packed two per byte, widening them to u8
+    ///
+    /// The first of the two voxels is in the low nibble. Rows of `width` voxels start on a byte
+    /// boundary, so a row of odd `width` ends with an unused nibble.
+    #[inline(always)]
+    fn read_u4_into(&mut self, buffer: &mut [u8], width: usize) -> Result<(), io::Error> {
+        if width == 0 {
+            return Ok(());
+        }
+        let mut packed = vec![0u8; width.div_ceil(2)];
+        for row in buffer.chunks_mut(width) {
+            let packed = &mut packed[..row.len().div_ceil(2)];
+            self.read_exact(packed)?;
+            for (pair, byte) in row.chunks_mut(2).zip(packed.iter()) {
+                pair[0] = byte & 0x0f;
+                if let Some(high) = pair.get_mut(1) {
+                    *high = byte >> 4;
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Reads an i16
     #[inline(always)]
     fn read_i16(&mut self) -> Result<i16, io::Error> {
@@ -57,6 +116,24 @@ pub trait EndianReader: Read {
         })
     }
 
+    #[inline(always)]
+    fn read_i16_into(&mut self, buffer: &mut [i16]) -> Result<(), io::Error> {
+        self.read_exact(bytecast::i16_as_ne_mut_bytes(buffer))?;
+        match self.byte_order() {
+            ByteOrder::LittleEndian => {
+                for n in buffer {
+                    *n = i16::from_le(*n);
+                }
+            }
+            ByteOrder::BigEndian => {
+                for n in buffer {
+                    *n = i16::from_be(*n);
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Reads an u32
     #[inline(always)]
     fn read_u32(&mut self) -> Result<u32, io::Error> {
@@ -97,6 +174,24 @@ pub trait EndianReader: Read {
         })
     }
 
+    #[inline(always)]
+    fn read_i32_into(&mut self, buffer: &mut [i32]) -> Result<(), io::Error> {
+        self.read_exact(bytecast::i32_as_ne_mut_bytes(buffer))?;
+        match self.byte_order() {
+            ByteOrder::LittleEndian => {
+                for n in buffer {
+                    *n = i32::from_le(*n);
+                }
+            }
+            ByteOrder::BigEndian => {
+                for n in buffer {
+                    *n = i32::from_be(*n);
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Reads an u64
     #[inline(always)]
     fn read_u64(&mut self) -> Result<u64, io::Error> {
@@ -155,6 +250,25 @@ pub trait EndianReader: Read {
         Ok(())
     }
 
+    /// Reads 16 bit IEEE floats, widening them to f32
+    #[inline(always)]
+    fn read_f16_into(&mut self, buffer: &mut [f32]) -> Result<(), io::Error> {
+        let len = buffer.len();
+        let bytes = bytecast::f32_as_ne_mut_bytes(buffer);
+        // The halves are read into the front of the buffer and widened back to front, so that no
+        // half is overwritten before it has been converted.
+        self.read_exact(&mut bytes[..2 * len])?;
+        for i in (0..len).rev() {
+            let n = [bytes[2 * i], bytes[2 * i + 1]];
+            let bits = match self.byte_order() {
+                ByteOrder::LittleEndian => u16::from_le_bytes(n),
+                ByteOrder::BigEndian => u16::from_be_bytes(n),
+            };
+            bytes[4 * i..4 * i + 4].copy_from_slice(&f16_to_f32(bits).to_ne_bytes());
+        }
+        Ok(())
+    }
+
     /// Reads an f64
     #[inline(always)]
     fn read_f64(&mut self) -> Result<f64, io::Error> {
@@ -185,9 +299,36 @@ pub trait EndianReader: Read {
     }
 }
 
+/// Converts the bit pattern of a 16 bit IEEE float to an f32
 ///
-/// ## SmartReader Reader
-///
+/// The conversion is exact, subnormals are normalized and infinities and NaNs (including the NaN
+/// payload) are preserved.
+pub(crate) fn f16_to_f32(bits: u16) -> f32 {
+    let sign = u32::from(bits & 0x8000) << 16;
+    let exponent = u32::from((bits >> 10) & 0x1f);
+    let mantissa = u32::from(bits & 0x3ff);
+
+    let bits = match (exponent, mantissa) {
+        (0, 0) => sign,
+        (0, _) => {
+            // Subnormal, shift the mantissa until the implicit bit is set
+            let mut exponent = 127 - 15 + 1;
+            let mut mantissa = mantissa;
+            while mantissa & 0x400 == 0 {
+                mantissa <<= 1;
+                exponent -= 1;
+            }
+            sign | (exponent << 23) | ((mantissa & 0x3ff) << 13)
+        }
+        (0x1f, _) => sign | 0x7f80_0000 | (mantissa << 13),
+        _ => sign | ((exponent + 127 - 15) << 23) | (mantissa << 13),
+    };
+    f32::from_bits(bits)
+}
+
+//
+// ## SmartReader Reader
+//
 
 /// Reader that is aware of the byte order.
 #[derive(Debug)]
@@ -0,0 +1,460 @@
+use super::{Mode, MrcError, MrcResult, MrcUnsupportedError, MrcUsageError};
+use std::convert::TryFrom;
+use std::io::{Seek, SeekFrom, Write};
+
+use crate::decoder::header::{
+    DensityStatistics, ExtendedHeaderType, Extra, Header, Origin, HEADER_SIZE, LABEL_SIZE,
+    NUM_LABELS,
+};
+use crate::decoder::stream::f16_to_f32;
+use crate::decoder::ByteOrder;
+use crate::extended::ccp4::{self, SymmetryOperator};
+
+pub(crate) mod statistics;
+pub mod stream;
+
+use self::statistics::StatisticsAccumulator;
+use self::stream::{f32_to_f16, EndianWriter, SmartWriter};
+
+/// Data to be encoded
+///
+/// The variants mirror `DecodingBuffer`, so that a decoded volume can be written back as is.
+pub enum EncodingBuffer<'a> {
+    /// A slice of unsigned bytes
+    U8(&'a [u8]),
+    /// A slice of unsigned words
+    U16(&'a [u16]),
+    /// A slice of 32 bit IEEE floats
+    F32(&'a [f32]),
+    /// A slice of 16 bit IEEE floats, stored as their bit patterns
+    F16(&'a [u16]),
+    /// A slice of signed bytes
+    I8(&'a [i8]),
+    /// A slice of signed words
+    I16(&'a [i16]),
+    /// A slice of complex numbers with signed word components, interleaved as real, imaginary
+    ComplexI16(&'a [i16]),
+    /// A slice of complex numbers with 32 bit IEEE float components, interleaved as real,
+    /// imaginary
+    ComplexF32(&'a [f32]),
+}
+
+impl<'a> EncodingBuffer<'a> {
+    fn len(&self) -> usize {
+        match *self {
+            EncodingBuffer::U8(buf) => buf.len(),
+            EncodingBuffer::U16(buf) => buf.len(),
+            EncodingBuffer::F32(buf) => buf.len(),
+            EncodingBuffer::F16(buf) => buf.len(),
+            EncodingBuffer::I8(buf) => buf.len(),
+            EncodingBuffer::I16(buf) => buf.len(),
+            EncodingBuffer::ComplexI16(buf) => buf.len(),
+            EncodingBuffer::ComplexF32(buf) => buf.len(),
+        }
+    }
+
+    /// Whether the buffer holds data that can be stored as `mode`
+    fn matches(&self, mode: Mode) -> bool {
+        matches!(
+            (mode, self),
+            (Mode::Mode0, EncodingBuffer::I8(_))
+                | (Mode::Mode0, EncodingBuffer::U8(_))
+                | (Mode::Mode1, EncodingBuffer::I16(_))
+                | (Mode::Mode2, EncodingBuffer::F32(_))
+                | (Mode::Mode3, EncodingBuffer::ComplexI16(_))
+                | (Mode::Mode4, EncodingBuffer::ComplexF32(_))
+                | (Mode::Mode6, EncodingBuffer::U16(_))
+                | (Mode::Mode12, EncodingBuffer::F16(_))
+                | (Mode::Mode12, EncodingBuffer::F32(_))
+                | (Mode::Mode16, EncodingBuffer::U8(_))
+                | (Mode::Mode101, EncodingBuffer::U8(_))
+        )
+    }
+}
+
+/// The representation of a MRC encoder
+///
+/// The encoder is configured with the `with_*` methods and then writes a single volume, either at
+/// once with `write_volume` or in parts through `new_volume`.
+#[derive(Debug)]
+pub struct Encoder<W>
+where
+    W: Write + Seek,
+{
+    writer: SmartWriter<W>,
+    start: [i32; 3],
+    sampling: Option<[usize; 3]>,
+    cell_lengths: Option<[f32; 3]>,
+    cell_angles: [f32; 3],
+    space_group: Option<i32>,
+    origin: [f32; 3],
+    labels: Vec<String>,
+    extended_header_type: Option<ExtendedHeaderType>,
+    extended_header: Vec<u8>,
+    compute_statistics: bool,
+}
+
+impl<W: Write + Seek> Encoder<W> {
+    /// Create a new encoder that encodes to the stream ```w```
+    pub fn new(w: W) -> Encoder<W> {
+        Encoder {
+            writer: SmartWriter::wrap(w, ByteOrder::LittleEndian),
+            start: [0; 3],
+            sampling: None,
+            cell_lengths: None,
+            cell_angles: [90.0; 3],
+            space_group: None,
+            origin: [0.0; 3],
+            labels: Vec::new(),
+            extended_header_type: None,
+            extended_header: Vec::new(),
+            compute_statistics: true,
+        }
+    }
+
+    /// Byte order of the written file, little endian by default
+    pub fn with_byte_order(mut self, byte_order: ByteOrder) -> Encoder<W> {
+        self.writer.byte_order = byte_order;
+        self
+    }
+
+    /// Numbers of the first column, row and section in the map, zero by default
+    pub fn with_start(mut self, start: [i32; 3]) -> Encoder<W> {
+        self.start = start;
+        self
+    }
+
+    /// Sampling grid of the unit cell, the dimensions of the volume by default
+    pub fn with_sampling(mut self, sampling: [usize; 3]) -> Encoder<W> {
+        self.sampling = Some(sampling);
+        self
+    }
+
+    /// Cell lengths in angstroms and cell angles in degrees
+    ///
+    /// By default, the cell lengths are equal to the sampling grid (a voxel size of 1 angstrom)
+    /// and the angles are 90 degrees.
+    pub fn with_cell(mut self, lengths: [f32; 3], angles: [f32; 3]) -> Encoder<W> {
+        self.cell_lengths = Some(lengths);
+        self.cell_angles = angles;
+        self
+    }
+
+    /// Space group number, by default 0 for images and 1 for volumes
+    pub fn with_space_group(mut self, space_group: i32) -> Encoder<W> {
+        self.space_group = Some(space_group);
+        self
+    }
+
+    /// Origin in X, Y and Z in angstroms
+    pub fn with_origin(mut self, origin: [f32; 3]) -> Encoder<W> {
+        self.origin = origin;
+        self
+    }
+
+    /// Adds a text label
+    ///
+    /// The header holds at most 10 labels of 80 characters; surplus labels are dropped and longer
+    /// ones truncated.
+    pub fn with_label(mut self, label: &str) -> Encoder<W> {
+        if self.labels.len() < NUM_LABELS {
+            let end = (0..=label.len().min(LABEL_SIZE))
+                .rev()
+                .find(|&i| label.is_char_boundary(i))
+                .unwrap_or(0);
+            self.labels.push(label[..end].to_string());
+        }
+        self
+    }
+
+    /// Extended header written between the main header and the data block
+    pub fn with_extended_header(
+        mut self,
+        ext_type: Option<ExtendedHeaderType>,
+        data: Vec<u8>,
+    ) -> Encoder<W> {
+        self.extended_header_type = ext_type;
+        self.extended_header = data;
+        self
+    }
+
+    /// Symmetry operators of the space group, written as `CCP4` records to the extended header
+    ///
+    /// Replaces any extended header set before. The space group number is set separately by
+    /// `with_space_group`.
+    pub fn with_symmetry_operators(mut self, operators: &[SymmetryOperator]) -> Encoder<W> {
+        self.extended_header_type = Some(ExtendedHeaderType::CCP4);
+        self.extended_header = ccp4::write_records(operators);
+        self
+    }
+
+    /// Whether to compute the density statistics (`amin`, `amax`, `amean`, `rms`) of the data,
+    /// enabled by default
+    ///
+    /// The statistics are computed in a single pass over the data as it is written. For huge
+    /// stacks where even that is too expensive, the statistics are instead marked as not
+    /// determined.
+    pub fn with_density_statistics(mut self, compute: bool) -> Encoder<W> {
+        self.compute_statistics = compute;
+        self
+    }
+
+    /// Consumes the encoder, returning the underlying stream
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner()
+    }
+
+    /// Builds the main header of a volume with the configured metadata
+    fn header(&self, mode: Mode, dimensions: [usize; 3]) -> MrcResult<Header> {
+        let to_i32 = |n: usize| i32::try_from(n).map_err(|_| MrcError::LimitsExceeded);
+        let [nx, ny, nz] = dimensions;
+        let [mx, my, mz] = self.sampling.unwrap_or(dimensions);
+        let [xlen, ylen, zlen] = self
+            .cell_lengths
+            .unwrap_or([mx as f32, my as f32, mz as f32]);
+        let [alpha, beta, gama] = self.cell_angles;
+        let ispg = self.space_group.unwrap_or(if nz == 1 { 0 } else { 1 });
+        // Completed by the `VolumeEncoder` once all the data has been written
+        let statistics = DensityStatistics::undetermined();
+
+        Ok(Header {
+            nx: to_i32(nx)?,
+            ny: to_i32(ny)?,
+            nz: to_i32(nz)?,
+            mode: i32::try_from(mode)?,
+            nxstart: self.start[0],
+            nystart: self.start[1],
+            nzstart: self.start[2],
+            mx: to_i32(mx)?,
+            my: to_i32(my)?,
+            mz: to_i32(mz)?,
+            xlen,
+            ylen,
+            zlen,
+            alpha,
+            beta,
+            gama,
+            mapc: 1,
+            mapr: 2,
+            maps: 3,
+            amin: statistics.min,
+            amax: statistics.max,
+            amean: statistics.mean,
+            ispg,
+            nsymbt: to_i32(self.extended_header.len())?,
+            extra: Extra {
+                ive_id: 0,
+                ext_type: self
+                    .extended_header_type
+                    .as_ref()
+                    .map_or(String::new(), |t| t.code().to_string()),
+                nversion: 20140,
+                nint: 0,
+                nreal: 0,
+                imod_stamp: 0,
+                imod_flags: 0,
+            },
+            origin: Origin {
+                xorg: self.origin[0],
+                yorg: self.origin[1],
+                zorg: self.origin[2],
+            },
+            map: "MAP ".to_string(),
+            mach_st: self.writer.byte_order.machine_stamp(),
+            rms: statistics.rms,
+            label: self.labels.clone(),
+        })
+    }
+
+    /// Starts writing a volume of `dimensions` (`nx`, `ny`, `nz`) in `mode`
+    ///
+    /// The data is then written with `VolumeEncoder::write_data` and the volume completed with
+    /// `VolumeEncoder::finish`.
+    pub fn new_volume(
+        &mut self,
+        mode: Mode,
+        dimensions: [usize; 3],
+    ) -> MrcResult<VolumeEncoder<'_, W>> {
+        let header = self.header(mode, dimensions)?;
+        let expected = dimensions
+            .iter()
+            .try_fold(mode.samples_per_voxel(), |n, &d| n.checked_mul(d))
+            .ok_or(MrcError::LimitsExceeded)?;
+
+        // The header is only complete once all the data has been written. A placeholder reserves
+        // its space in the meantime.
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.write_all(&[0u8; HEADER_SIZE as usize])?;
+        self.writer.write_all(&self.extended_header)?;
+
+        let statistics = if self.compute_statistics {
+            Some(StatisticsAccumulator::new())
+        } else {
+            None
+        };
+
+        Ok(VolumeEncoder {
+            encoder: self,
+            header,
+            statistics,
+            mode,
+            dimensions,
+            expected,
+            written: 0,
+        })
+    }
+
+    /// Writes a whole volume of `dimensions` (`nx`, `ny`, `nz`) in `mode`
+    pub fn write_volume(
+        &mut self,
+        mode: Mode,
+        dimensions: [usize; 3],
+        data: EncodingBuffer,
+    ) -> MrcResult<()> {
+        let mut volume = self.new_volume(mode, dimensions)?;
+        volume.write_data(data)?;
+        volume.finish()
+    }
+}
+
+/// A volume being written by an `Encoder`
+pub struct VolumeEncoder<'a, W>
+where
+    W: Write + Seek,
+{
+    encoder: &'a mut Encoder<W>,
+    header: Header,
+    statistics: Option<StatisticsAccumulator>,
+    mode: Mode,
+    dimensions: [usize; 3],
+    expected: usize,
+    written: usize,
+}
+
+impl<'a, W: Write + Seek> VolumeEncoder<'a, W> {
+    /// Appends `data` to the data block
+    ///
+    /// Voxels are ordered with X changing fastest and Z slowest. RGB voxels (`Mode16`) are given as
+    /// three consecutive samples, complex voxels (`Mode3`, `Mode4`) as their real and imaginary
+    /// parts and 4 bit voxels (`Mode101`) as one byte each, in whole rows. Each call takes whole
+    /// voxels.
+    ///
+    /// `Mode0` takes signed or unsigned bytes, but the same for the whole volume. Unsigned bytes
+    /// are marked by the IMOD flags, as IMOD does.
+    pub fn write_data(&mut self, data: EncodingBuffer) -> MrcResult<()> {
+        if !data.matches(self.mode) {
+            return Err(MrcUnsupportedError::UnsupportedDataType.into());
+        }
+        let signed = matches!(data, EncodingBuffer::I8(_));
+        let switch_signedness = self.mode == Mode::Mode0 && signed != self.header.signed_bytes();
+        if switch_signedness && self.written > 0 {
+            return Err(MrcUnsupportedError::UnsupportedDataType.into());
+        }
+        let len = data.len();
+        // Statistics of complex voxels pair the samples of a single call
+        if !len.is_multiple_of(self.mode.samples_per_voxel()) {
+            return Err(MrcUsageError::PartialVoxel.into());
+        }
+        if self.mode == Mode::Mode101 {
+            let width = self.dimensions[0];
+            if width == 0 || !len.is_multiple_of(width) {
+                return Err(MrcUsageError::PartialRow.into());
+            }
+        }
+        if self.written + len > self.expected {
+            return Err(MrcUsageError::DataSizeMismatch {
+                expected: self.expected,
+                actual: self.written + len,
+            }
+            .into());
+        }
+
+        // Only data that is written counts towards the header
+        if switch_signedness {
+            self.header.set_signed_bytes(signed);
+        }
+        if let Some(statistics) = self.statistics.as_mut() {
+            accumulate(statistics, self.mode, &data);
+        }
+
+        let writer = &mut self.encoder.writer;
+        match data {
+            EncodingBuffer::U8(buf) if self.mode == Mode::Mode101 => {
+                let width = self.dimensions[0];
+                let mut packed = vec![0u8; width.div_ceil(2)];
+                for row in buf.chunks(width) {
+                    for (byte, pair) in packed.iter_mut().zip(row.chunks(2)) {
+                        *byte = (pair[0] & 0x0f) | (pair.get(1).copied().unwrap_or(0) << 4);
+                    }
+                    writer.write_all(&packed)?;
+                }
+            }
+            EncodingBuffer::U8(buf) => writer.write_all(buf)?,
+            EncodingBuffer::I8(buf) => writer.write_i8_from(buf)?,
+            EncodingBuffer::U16(buf) | EncodingBuffer::F16(buf) => writer.write_u16_from(buf)?,
+            EncodingBuffer::I16(buf) | EncodingBuffer::ComplexI16(buf) => {
+                writer.write_i16_from(buf)?
+            }
+            EncodingBuffer::F32(buf) if self.mode == Mode::Mode12 => writer.write_f16_from(buf)?,
+            EncodingBuffer::F32(buf) | EncodingBuffer::ComplexF32(buf) => {
+                writer.write_f32_from(buf)?
+            }
+        }
+        self.written += len;
+        Ok(())
+    }
+
+    /// Completes the volume by writing its header
+    pub fn finish(mut self) -> MrcResult<()> {
+        if self.written != self.expected {
+            return Err(MrcUsageError::DataSizeMismatch {
+                expected: self.expected,
+                actual: self.written,
+            }
+            .into());
+        }
+        if let Some(statistics) = &self.statistics {
+            self.header.set_density_statistics(statistics.statistics());
+        }
+
+        let writer = &mut self.encoder.writer;
+        let end = writer.stream_position()?;
+        writer.seek(SeekFrom::Start(0))?;
+        self.header.write(writer)?;
+        writer.seek(SeekFrom::Start(end))?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Adds the values of `data`, as they are stored in `mode`, to `statistics`
+///
+/// Complex voxels contribute their magnitude, RGB voxels each of their samples.
+pub(crate) fn accumulate(
+    statistics: &mut StatisticsAccumulator,
+    mode: Mode,
+    data: &EncodingBuffer,
+) {
+    match *data {
+        EncodingBuffer::U8(buf) if mode == Mode::Mode101 => {
+            statistics.extend(buf.iter().map(|&n| f64::from(n & 0x0f)))
+        }
+        EncodingBuffer::U8(buf) => statistics.extend(buf.iter().map(|&n| f64::from(n))),
+        EncodingBuffer::I8(buf) => statistics.extend(buf.iter().map(|&n| f64::from(n))),
+        EncodingBuffer::U16(buf) => statistics.extend(buf.iter().map(|&n| f64::from(n))),
+        EncodingBuffer::I16(buf) => statistics.extend(buf.iter().map(|&n| f64::from(n))),
+        EncodingBuffer::F16(buf) => {
+            statistics.extend(buf.iter().map(|&n| f64::from(f16_to_f32(n))))
+        }
+        EncodingBuffer::F32(buf) if mode == Mode::Mode12 => {
+            statistics.extend(buf.iter().map(|&n| f64::from(f16_to_f32(f32_to_f16(n)))))
+        }
+        EncodingBuffer::F32(buf) => statistics.extend(buf.iter().map(|&n| f64::from(n))),
+        EncodingBuffer::ComplexI16(buf) => {
+            statistics.extend_complex(buf.iter().map(|&n| f64::from(n)))
+        }
+        EncodingBuffer::ComplexF32(buf) => {
+            statistics.extend_complex(buf.iter().map(|&n| f64::from(n)))
+        }
+    }
+}
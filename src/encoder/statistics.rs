@@ -0,0 +1,72 @@
+//! Streaming computation of the density statistics
+
+use crate::decoder::header::DensityStatistics;
+
+/// Accumulates the density statistics of a data block in a single pass
+///
+/// The mean and the rms deviation are updated with Welford's algorithm in f64, which stays stable
+/// for stacks of billions of voxels where the naive sum of squares loses all precision. NaNs are
+/// skipped.
+#[derive(Debug, Clone)]
+pub(crate) struct StatisticsAccumulator {
+    count: u64,
+    min: f64,
+    max: f64,
+    mean: f64,
+    /// Sum of the squared deviations from the current mean
+    m2: f64,
+}
+
+impl StatisticsAccumulator {
+    pub(crate) fn new() -> StatisticsAccumulator {
+        StatisticsAccumulator {
+            count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn add(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    pub(crate) fn extend<I: IntoIterator<Item = f64>>(&mut self, values: I) {
+        for value in values {
+            self.add(value);
+        }
+    }
+
+    /// Adds complex values, interleaved as real, imaginary, by their magnitude
+    pub(crate) fn extend_complex<I: IntoIterator<Item = f64>>(&mut self, values: I) {
+        let mut values = values.into_iter();
+        while let (Some(re), Some(im)) = (values.next(), values.next()) {
+            self.add(re.hypot(im));
+        }
+    }
+
+    /// The statistics of the values added so far
+    ///
+    /// Without any values, the statistics are marked as not determined.
+    pub(crate) fn statistics(&self) -> DensityStatistics {
+        if self.count == 0 {
+            return DensityStatistics::undetermined();
+        }
+        DensityStatistics {
+            min: self.min as f32,
+            max: self.max as f32,
+            mean: self.mean as f32,
+            rms: (self.m2 / self.count as f64).sqrt() as f32,
+        }
+    }
+}
@@ -0,0 +1,382 @@
+//! All IO functionality needed for MRC encoding
+
+use crate::bytecast;
+use crate::decoder::ByteOrder;
+use std::io::{self, Seek, Write};
+
+/// Size of the scratch buffer used for byte swapping
+const SCRATCH_SIZE: usize = 4096;
+
+/// Writer that is aware of the byte order.
+pub trait EndianWriter: Write {
+    /// Byte order that should be adhered to
+    fn byte_order(&self) -> ByteOrder;
+
+    /// Writes elements of `size` bytes given in native byte order, swapping them if needed
+    ///
+    /// Swapped elements are gathered in a fixed scratch buffer, so that large slices are written
+    /// in few calls without allocating.
+    #[inline(always)]
+    fn write_ne_bytes(&mut self, bytes: &[u8], size: usize) -> Result<(), io::Error> {
+        if size == 1 || self.byte_order() == ByteOrder::native() {
+            return self.write_all(bytes);
+        }
+        let mut scratch = [0u8; SCRATCH_SIZE];
+        for chunk in bytes.chunks(SCRATCH_SIZE - SCRATCH_SIZE % size) {
+            let scratch = &mut scratch[..chunk.len()];
+            for (swapped, n) in scratch.chunks_exact_mut(size).zip(chunk.chunks_exact(size)) {
+                for (to, from) in swapped.iter_mut().zip(n.iter().rev()) {
+                    *to = *from;
+                }
+            }
+            self.write_all(scratch)?;
+        }
+        Ok(())
+    }
+
+    /// Writes an u16
+    #[inline(always)]
+    fn write_u16(&mut self, n: u16) -> Result<(), io::Error> {
+        self.write_all(&match self.byte_order() {
+            ByteOrder::LittleEndian => n.to_le_bytes(),
+            ByteOrder::BigEndian => n.to_be_bytes(),
+        })
+    }
+
+    #[inline(always)]
+    fn write_u16_from(&mut self, buffer: &[u16]) -> Result<(), io::Error> {
+        self.write_ne_bytes(bytecast::u16_as_ne_bytes(buffer), 2)
+    }
+
+    #[inline(always)]
+    fn write_i8_from(&mut self, buffer: &[i8]) -> Result<(), io::Error> {
+        self.write_all(bytecast::i8_as_ne_bytes(buffer))
+    }
+
+    /// Writes an i16
+    #[inline(always)]
+    fn write_i16(&mut self, n: i16) -> Result<(), io::Error> {
+        self.write_all(&match self.byte_order() {
+            ByteOrder::LittleEndian => n.to_le_bytes(),
+            ByteOrder::BigEndian => n.to_be_bytes(),
+        })
+    }
+
+    #[inline(always)]
+    fn write_i16_from(&mut self, buffer: &[i16]) -> Result<(), io::Error> {
+        self.write_ne_bytes(bytecast::i16_as_ne_bytes(buffer), 2)
+    }
+
+    /// Writes an u32
+    #[inline(always)]
+    fn write_u32(&mut self, n: u32) -> Result<(), io::Error> {
+        self.write_all(&match self.byte_order() {
+            ByteOrder::LittleEndian => n.to_le_bytes(),
+            ByteOrder::BigEndian => n.to_be_bytes(),
+        })
+    }
+
+    #[inline(always)]
+    fn write_u32_from(&mut self, buffer: &[u32]) -> Result<(), io::Error> {
+        self.write_ne_bytes(bytecast::u32_as_ne_bytes(buffer), 4)
+    }
+
+    /// Writes an i32
+    #[inline(always)]
+    fn write_i32(&mut self, n: i32) -> Result<(), io::Error> {
+        self.write_all(&match self.byte_order() {
+            ByteOrder::LittleEndian => n.to_le_bytes(),
+            ByteOrder::BigEndian => n.to_be_bytes(),
+        })
+    }
+
+    #[inline(always)]
+    fn write_i32_from(&mut self, buffer: &[i32]) -> Result<(), io::Error> {
+        self.write_ne_bytes(bytecast::i32_as_ne_bytes(buffer), 4)
+    }
+
+    /// Writes an u64
+    #[inline(always)]
+    fn write_u64(&mut self, n: u64) -> Result<(), io::Error> {
+        self.write_all(&match self.byte_order() {
+            ByteOrder::LittleEndian => n.to_le_bytes(),
+            ByteOrder::BigEndian => n.to_be_bytes(),
+        })
+    }
+
+    #[inline(always)]
+    fn write_u64_from(&mut self, buffer: &[u64]) -> Result<(), io::Error> {
+        self.write_ne_bytes(bytecast::u64_as_ne_bytes(buffer), 8)
+    }
+
+    /// Writes an f32
+    #[inline(always)]
+    fn write_f32(&mut self, n: f32) -> Result<(), io::Error> {
+        self.write_u32(n.to_bits())
+    }
+
+    #[inline(always)]
+    fn write_f32_from(&mut self, buffer: &[f32]) -> Result<(), io::Error> {
+        self.write_ne_bytes(bytecast::f32_as_ne_bytes(buffer), 4)
+    }
+
+    /// Writes f32s as 16 bit IEEE floats
+    #[inline(always)]
+    fn write_f16_from(&mut self, buffer: &[f32]) -> Result<(), io::Error> {
+        let mut halves = [0u16; SCRATCH_SIZE / 2];
+        for chunk in buffer.chunks(halves.len()) {
+            for (half, &n) in halves.iter_mut().zip(chunk) {
+                *half = f32_to_f16(n);
+            }
+            self.write_u16_from(&halves[..chunk.len()])?;
+        }
+        Ok(())
+    }
+
+    /// Writes an f64
+    #[inline(always)]
+    fn write_f64(&mut self, n: f64) -> Result<(), io::Error> {
+        self.write_all(&match self.byte_order() {
+            ByteOrder::LittleEndian => n.to_bits().to_le_bytes(),
+            ByteOrder::BigEndian => n.to_bits().to_be_bytes(),
+        })
+    }
+
+    #[inline(always)]
+    fn write_f64_from(&mut self, buffer: &[f64]) -> Result<(), io::Error> {
+        self.write_ne_bytes(bytecast::f64_as_ne_bytes(buffer), 8)
+    }
+}
+
+/// Converts an f32 to the bit pattern of the nearest 16 bit IEEE float
+///
+/// Ties are rounded to even, values out of range become infinities and NaNs stay NaNs.
+pub(crate) fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        let nan = if mantissa == 0 {
+            0
+        } else {
+            0x200 | (mantissa >> 13) as u16
+        };
+        return sign | 0x7c00 | nan;
+    }
+
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+
+    // Keep the top bits of the mantissa (with the implicit bit for subnormals) and round the rest
+    let (half, mantissa, shift) = if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+        (0, mantissa | 0x80_0000, (14 - exponent) as u32)
+    } else {
+        ((exponent as u32) << 10, mantissa, 13)
+    };
+    let half = half | (mantissa >> shift);
+    let rest = mantissa & ((1 << shift) - 1);
+    let halfway = 1 << (shift - 1);
+    let round_up = rest > halfway || (rest == halfway && half & 1 == 1);
+    // A carry out of the mantissa correctly increments the exponent, up to infinity
+    sign | (half + round_up as u32) as u16
+}
+
+//
+// ## SmartWriter Writer
+//
+
+/// Writer that is aware of the byte order.
+#[derive(Debug)]
+pub struct SmartWriter<W>
+where
+    W: Write + Seek,
+{
+    writer: W,
+    pub byte_order: ByteOrder,
+}
+
+impl<W> SmartWriter<W>
+where
+    W: Write + Seek,
+{
+    /// Wraps a writer
+    pub fn wrap(writer: W, byte_order: ByteOrder) -> SmartWriter<W> {
+        SmartWriter { writer, byte_order }
+    }
+
+    /// Unwraps the writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W> EndianWriter for SmartWriter<W>
+where
+    W: Write + Seek,
+{
+    #[inline(always)]
+    fn byte_order(&self) -> ByteOrder {
+        self.byte_order
+    }
+}
+
+impl<W: Write + Seek> Write for SmartWriter<W> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<W: Write + Seek> Seek for SmartWriter<W> {
+    #[inline]
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.writer.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::stream::{f16_to_f32, EndianReader, SmartReader};
+    use std::io::Cursor;
+
+    fn written<F>(byte_order: ByteOrder, write: F) -> Vec<u8>
+    where
+        F: FnOnce(&mut SmartWriter<Cursor<Vec<u8>>>) -> Result<(), io::Error>,
+    {
+        let mut writer = SmartWriter::wrap(Cursor::new(Vec::new()), byte_order);
+        write(&mut writer).unwrap();
+        writer.into_inner().into_inner()
+    }
+
+    #[test]
+    fn byte_order_of_scalars() {
+        let le = written(ByteOrder::LittleEndian, |w| w.write_i32(0x0102_0304));
+        let be = written(ByteOrder::BigEndian, |w| w.write_i32(0x0102_0304));
+        assert_eq!(le, [4, 3, 2, 1]);
+        assert_eq!(be, [1, 2, 3, 4]);
+        let be = written(ByteOrder::BigEndian, |w| w.write_f32(1.0));
+        assert_eq!(be, [0x3f, 0x80, 0, 0]);
+    }
+
+    #[test]
+    fn round_trip_against_the_reader() {
+        let words: Vec<u16> = (0..3000).map(|n| n * 21).collect();
+        let floats: Vec<f32> = (0..100).map(|n| n as f32 / 3.0 - 7.0).collect();
+        let doubles: Vec<f64> = (0..100).map(|n| n as f64 / 7.0 - 3.0).collect();
+        for &byte_order in &[ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            let bytes = written(byte_order, |w| {
+                w.write_u16(0xfedc)?;
+                w.write_i16(-12345)?;
+                w.write_u32(0xdead_beef)?;
+                w.write_i32(-7)?;
+                w.write_u64(0x0123_4567_89ab_cdef)?;
+                w.write_f32(-1.5)?;
+                w.write_f64(1e-300)?;
+                w.write_u16_from(&words)?;
+                w.write_u32_from(&[1, u32::MAX])?;
+                w.write_i32_from(&[i32::MIN, 3])?;
+                w.write_u64_from(&[u64::MAX, 1 << 40])?;
+                w.write_f32_from(&floats)?;
+                w.write_f64_from(&doubles)
+            });
+
+            let mut reader = SmartReader::wrap(Cursor::new(bytes), byte_order);
+            assert_eq!(reader.read_u16().unwrap(), 0xfedc);
+            assert_eq!(reader.read_i16().unwrap(), -12345);
+            assert_eq!(reader.read_u32().unwrap(), 0xdead_beef);
+            assert_eq!(reader.read_i32().unwrap(), -7);
+            assert_eq!(reader.read_u64().unwrap(), 0x0123_4567_89ab_cdef);
+            assert_eq!(reader.read_f32().unwrap(), -1.5);
+            assert_eq!(reader.read_f64().unwrap(), 1e-300);
+            let mut read_words = vec![0; words.len()];
+            reader.read_u16_into(&mut read_words).unwrap();
+            assert_eq!(read_words, words);
+            let mut read_u32s = [0; 2];
+            reader.read_u32_into(&mut read_u32s).unwrap();
+            assert_eq!(read_u32s, [1, u32::MAX]);
+            let mut read_i32s = [0; 2];
+            reader.read_i32_into(&mut read_i32s).unwrap();
+            assert_eq!(read_i32s, [i32::MIN, 3]);
+            let mut read_u64s = [0; 2];
+            reader.read_u64_into(&mut read_u64s).unwrap();
+            assert_eq!(read_u64s, [u64::MAX, 1 << 40]);
+            let mut read_floats = vec![0.0; floats.len()];
+            reader.read_f32_into(&mut read_floats).unwrap();
+            assert_eq!(read_floats, floats);
+            let mut read_doubles = vec![0.0; doubles.len()];
+            reader.read_f64_into(&mut read_doubles).unwrap();
+            assert_eq!(read_doubles, doubles);
+        }
+    }
+
+    #[test]
+    fn byte_order_of_slices_beyond_the_scratch_buffer() {
+        let data: Vec<i16> = (0..3000).map(|n| n * 7 - 10000).collect();
+        for &byte_order in &[ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            let bytes = written(byte_order, |w| w.write_i16_from(&data));
+            let expected: Vec<u8> = data
+                .iter()
+                .flat_map(|&n| match byte_order {
+                    ByteOrder::LittleEndian => n.to_le_bytes(),
+                    ByteOrder::BigEndian => n.to_be_bytes(),
+                })
+                .collect();
+            assert_eq!(bytes, expected);
+        }
+    }
+
+    #[test]
+    fn f16_edge_values() {
+        let cases: &[(f32, u16)] = &[
+            (0.0, 0x0000),
+            (-0.0, 0x8000),
+            (1.0, 0x3c00),
+            (-2.0, 0xc000),
+            (65504.0, 0x7bff),
+            // Rounds up past the largest finite half
+            (65520.0, 0x7c00),
+            (1e10, 0x7c00),
+            (f32::INFINITY, 0x7c00),
+            (f32::NEG_INFINITY, 0xfc00),
+            // Smallest normal and subnormal halves
+            (2f32.powi(-14), 0x0400),
+            (2f32.powi(-24), 0x0001),
+            // Halfway between 0 and the smallest subnormal rounds to even
+            (2f32.powi(-25), 0x0000),
+            (1.5 * 2f32.powi(-25), 0x0001),
+            (2f32.powi(-26), 0x0000),
+            // Ties between normal halves round to even
+            (1.0 + 2f32.powi(-11), 0x3c00),
+            (1.0 + 3.0 * 2f32.powi(-11), 0x3c02),
+        ];
+        for &(value, half) in cases {
+            assert_eq!(f32_to_f16(value), half, "{}", value);
+        }
+        assert!(f16_to_f32(f32_to_f16(f32::NAN)).is_nan());
+    }
+
+    #[test]
+    fn f16_round_trip() {
+        for half in 0..=u16::MAX {
+            let value = f16_to_f32(half);
+            if value.is_nan() {
+                assert!(f16_to_f32(f32_to_f16(value)).is_nan());
+            } else {
+                assert_eq!(f32_to_f16(value), half);
+            }
+        }
+    }
+}
@@ -1,8 +1,8 @@
 use std::error::Error;
 use std::fmt;
 use std::io;
-use std::string;
 
+use super::decoder::header::ExtendedHeaderType;
 use super::decoder::ifd::Value;
 use super::Mode;
 
@@ -18,6 +18,9 @@ pub enum MrcError {
     /// An I/O Error occurred while decoding the image.
     IoError(io::Error),
 
+    /// The Library was used incorrectly.
+    UsageError(MrcUsageError),
+
     /// The Limits of the Decoder is exceeded.
     LimitsExceeded,
 }
@@ -31,6 +34,8 @@ pub enum MrcFormatError {
     ByteExpected(Value),
     UnsignedIntegerExpected(Value),
     SignedIntegerExpected(Value),
+    InvalidMode(i32),
+    InvalidAxisMapping([i32; 3]),
     Format(String),
 }
 
@@ -45,6 +50,12 @@ impl fmt::Display for MrcFormatError {
             SignedIntegerExpected(ref val) => {
                 write!(fmt, "Expected signed integer, {:?} found.", val)
             }
+            InvalidMode(val) => write!(fmt, "Invalid mode {}.", val),
+            InvalidAxisMapping(val) => write!(
+                fmt,
+                "Invalid axis mapping {:?}, expected a permutation of 1, 2 and 3.",
+                val
+            ),
             Format(ref val) => write!(fmt, "Invalid format: {:?}.", val),
         }
     }
@@ -61,5 +72,159 @@ pub enum MrcUnsupportedError {
     UnsupportedDataType,
 }
 
+/// User attempted to use the Decoder or the Encoder in a way that is incompatible with a specific
+/// image.
+///
+/// For example: attempting to write more data than the dimensions of the volume allow.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MrcUsageError {
+    /// The amount of data does not match the dimensions of the volume
+    DataSizeMismatch { expected: usize, actual: usize },
+    /// Packed data (`Mode101`) can only be written in whole rows
+    PartialRow,
+    /// Complex (`Mode3`, `Mode4`) and RGB (`Mode16`) data can only be written in whole voxels
+    PartialVoxel,
+    /// All the sections of the volume have already been decoded
+    NoMoreSections,
+    /// The requested section is not in the volume
+    SectionOutOfBounds { section: usize, sections: usize },
+    /// The requested range along `axis` is not in the volume or is reversed
+    RegionOutOfBounds {
+        axis: char,
+        start: usize,
+        end: usize,
+        size: usize,
+    },
+    /// The extended header is not of the type whose metadata was requested
+    ExtendedHeaderMismatch {
+        expected: ExtendedHeaderType,
+        found: Option<ExtendedHeaderType>,
+    },
+    /// The file was not written by the software whose metadata was requested
+    SoftwareMismatch { expected: Mode, found: Option<Mode> },
+}
+
+impl fmt::Display for MrcUsageError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        use self::MrcUsageError::*;
+        match *self {
+            DataSizeMismatch { expected, actual } => write!(
+                fmt,
+                "Expected {} samples of data, {} were given.",
+                expected, actual
+            ),
+            PartialRow => write!(fmt, "Packed data has to be written in whole rows."),
+            PartialVoxel => write!(
+                fmt,
+                "Complex and RGB data has to be written in whole voxels."
+            ),
+            NoMoreSections => write!(fmt, "All the sections have been decoded."),
+            SectionOutOfBounds { section, sections } => write!(
+                fmt,
+                "Section {} requested, the volume has {} sections.",
+                section, sections
+            ),
+            RegionOutOfBounds {
+                axis,
+                start,
+                end,
+                size,
+            } => write!(
+                fmt,
+                "Range {}..{} along {} requested, the volume spans 0..{}.",
+                start, end, axis, size
+            ),
+            ExtendedHeaderMismatch {
+                ref expected,
+                ref found,
+            } => match found {
+                Some(found) => write!(
+                    fmt,
+                    "Expected an extended header of type {}, found {}.",
+                    expected.code(),
+                    found.code()
+                ),
+                None => write!(
+                    fmt,
+                    "Expected an extended header of type {}, the type is not set.",
+                    expected.code()
+                ),
+            },
+            SoftwareMismatch { expected, found } => match found {
+                Some(found) => write!(
+                    fmt,
+                    "Expected a file written by {:?}, found one written by {:?}.",
+                    expected, found
+                ),
+                None => write!(
+                    fmt,
+                    "Expected a file written by {:?}, the software is not recognized.",
+                    expected
+                ),
+            },
+        }
+    }
+}
+
 /// Result of an image decoding/encoding process
 pub type MrcResult<T> = Result<T, MrcError>;
+
+impl fmt::Display for MrcUnsupportedError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        use self::MrcUnsupportedError::*;
+        match *self {
+            UnsupportedMode(ref mode) => write!(fmt, "Unsupported mode {:?}.", mode),
+            UnsupportedDataType => write!(fmt, "Unsupported data type."),
+        }
+    }
+}
+
+impl fmt::Display for MrcError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            MrcError::FormatError(ref e) => write!(fmt, "Format error: {}", e),
+            MrcError::UnsupportedError(ref f) => write!(
+                fmt,
+                "The Decoder does not support the \
+                 image format `{}`",
+                f
+            ),
+            MrcError::IoError(ref e) => e.fmt(fmt),
+            MrcError::UsageError(ref e) => write!(fmt, "Usage error: {}", e),
+            MrcError::LimitsExceeded => write!(fmt, "The Decoder limits are exceeded"),
+        }
+    }
+}
+
+impl Error for MrcError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            MrcError::IoError(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MrcError {
+    fn from(err: io::Error) -> MrcError {
+        MrcError::IoError(err)
+    }
+}
+
+impl From<MrcFormatError> for MrcError {
+    fn from(err: MrcFormatError) -> MrcError {
+        MrcError::FormatError(err)
+    }
+}
+
+impl From<MrcUsageError> for MrcError {
+    fn from(err: MrcUsageError) -> MrcError {
+        MrcError::UsageError(err)
+    }
+}
+
+impl From<MrcUnsupportedError> for MrcError {
+    fn from(err: MrcUnsupportedError) -> MrcError {
+        MrcError::UnsupportedError(err)
+    }
+}
@@ -0,0 +1,282 @@
+//! Symmetry records of crystallographic maps (`CCP4`)
+//!
+//! The extended header holds text records of 80 characters, each with one or more symmetry
+//! operators separated by `*`. An operator gives the transformed fractional coordinates as in the
+//! International Tables, e.g. `-X,Y+1/2,-Z`.
+
+use crate::{MrcFormatError, MrcResult};
+use std::fmt;
+use std::str::FromStr;
+
+/// Length of a single symmetry record in bytes
+pub(crate) const RECORD_SIZE: usize = 80;
+
+/// Symmetry operator of a space group acting on fractional coordinates
+///
+/// A point `x` is mapped to `rotation * x + translation`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SymmetryOperator {
+    /// Rows of the rotation matrix
+    pub rotation: [[f64; 3]; 3],
+    /// Translation in fractions of the unit cell
+    pub translation: [f64; 3],
+}
+
+impl SymmetryOperator {
+    /// The identity operator `X,Y,Z`
+    pub fn identity() -> SymmetryOperator {
+        SymmetryOperator {
+            rotation: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            translation: [0.0; 3],
+        }
+    }
+
+    /// Applies the operator to the fractional coordinates `point`
+    pub fn apply(&self, point: [f64; 3]) -> [f64; 3] {
+        let mut result = self.translation;
+        for (value, row) in result.iter_mut().zip(self.rotation.iter()) {
+            *value += row
+                .iter()
+                .zip(point.iter())
+                .map(|(a, b)| a * b)
+                .sum::<f64>();
+        }
+        result
+    }
+}
+
+impl FromStr for SymmetryOperator {
+    type Err = MrcFormatError;
+
+    /// Parses an operator such as `-X+1/2, Y, -Z` or `x-y, x, 2z+0.5`
+    fn from_str(operator: &str) -> Result<SymmetryOperator, MrcFormatError> {
+        let invalid =
+            || MrcFormatError::Format(format!("invalid symmetry operator {:?}", operator));
+        let rows: Vec<&str> = operator.split(',').collect();
+        if rows.len() != 3 {
+            return Err(invalid());
+        }
+
+        let mut result = SymmetryOperator {
+            rotation: [[0.0; 3]; 3],
+            translation: [0.0; 3],
+        };
+        for (i, row) in rows.iter().enumerate() {
+            let row: String = row.chars().filter(|c| !c.is_whitespace()).collect();
+            if row.is_empty() {
+                return Err(invalid());
+            }
+            // Split into signed terms, e.g. `-X`, `+1/2`
+            let mut start = 0;
+            let bytes = row.as_bytes();
+            for end in (1..=bytes.len())
+                .filter(|&end| end == bytes.len() || (bytes[end] == b'+' || bytes[end] == b'-'))
+            {
+                let term = &row[start..end];
+                start = end;
+                let (sign, term) = match term.as_bytes()[0] {
+                    b'-' => (-1.0, &term[1..]),
+                    b'+' => (1.0, &term[1..]),
+                    _ => (1.0, term),
+                };
+                let axis = match term.chars().last() {
+                    Some('x') | Some('X') => Some(0),
+                    Some('y') | Some('Y') => Some(1),
+                    Some('z') | Some('Z') => Some(2),
+                    _ => None,
+                };
+                match axis {
+                    Some(axis) => {
+                        let coefficient = &term[..term.len() - 1];
+                        let coefficient = if coefficient.is_empty() {
+                            1.0
+                        } else {
+                            parse_number(coefficient).ok_or_else(invalid)?
+                        };
+                        result.rotation[i][axis] += sign * coefficient;
+                    }
+                    None => {
+                        result.translation[i] += sign * parse_number(term).ok_or_else(invalid)?
+                    }
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl fmt::Display for SymmetryOperator {
+    /// Formats the operator as in the International Tables, e.g. `-X,Y+1/2,-Z`
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (row, &translation)) in self.rotation.iter().zip(&self.translation).enumerate() {
+            if i > 0 {
+                write!(fmt, ",")?;
+            }
+            let mut empty = true;
+            for (&coefficient, axis) in row.iter().zip(&["X", "Y", "Z"]) {
+                if coefficient == 0.0 {
+                    continue;
+                }
+                let sign = if coefficient < 0.0 {
+                    "-"
+                } else if empty {
+                    ""
+                } else {
+                    "+"
+                };
+                match coefficient.abs() {
+                    1.0 => write!(fmt, "{}{}", sign, axis)?,
+                    magnitude => write!(fmt, "{}{}{}", sign, magnitude, axis)?,
+                }
+                empty = false;
+            }
+            if translation != 0.0 || empty {
+                let sign = if translation < 0.0 {
+                    "-"
+                } else if empty {
+                    ""
+                } else {
+                    "+"
+                };
+                write!(fmt, "{}{}", sign, Fraction(translation.abs()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Number written as a fraction with a small denominator if possible, e.g. `1/2` or `2/3`
+struct Fraction(f64);
+
+impl fmt::Display for Fraction {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for &denominator in &[1.0, 2.0, 3.0, 4.0, 6.0, 8.0, 12.0] {
+            let numerator = self.0 * denominator;
+            if (numerator - numerator.round()).abs() < 1e-6 {
+                return match denominator {
+                    1.0 => write!(fmt, "{}", numerator.round()),
+                    _ => write!(fmt, "{}/{}", numerator.round(), denominator),
+                };
+            }
+        }
+        write!(fmt, "{}", self.0)
+    }
+}
+
+/// Parses a decimal number or a fraction such as `1/2`
+fn parse_number(number: &str) -> Option<f64> {
+    match number.find('/') {
+        Some(i) => {
+            let numerator: f64 = number[..i].parse().ok()?;
+            let denominator: f64 = number[i + 1..].parse().ok()?;
+            if denominator == 0.0 {
+                return None;
+            }
+            Some(numerator / denominator)
+        }
+        None => number.parse().ok(),
+    }
+}
+
+/// Reads the symmetry operators from the records in the extended header `bytes`
+pub(crate) fn read_records(bytes: &[u8]) -> MrcResult<Vec<SymmetryOperator>> {
+    if !bytes.len().is_multiple_of(RECORD_SIZE) {
+        return Err(MrcFormatError::Format(format!(
+            "symmetry records of {} bytes are not a multiple of {} characters",
+            bytes.len(),
+            RECORD_SIZE
+        ))
+        .into());
+    }
+    let mut operators = Vec::new();
+    for record in bytes.chunks_exact(RECORD_SIZE) {
+        let record = std::str::from_utf8(record)
+            .map_err(|_| MrcFormatError::Format("symmetry record is not text".to_string()))?;
+        for operator in record
+            .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+            .split('*')
+        {
+            if !operator.trim().is_empty() {
+                operators.push(operator.parse()?);
+            }
+        }
+    }
+    Ok(operators)
+}
+
+/// Writes the symmetry operators as records of the extended header, one operator per record
+pub(crate) fn write_records(operators: &[SymmetryOperator]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(operators.len() * RECORD_SIZE);
+    for operator in operators {
+        let mut record = operator.to_string().into_bytes();
+        record.resize(RECORD_SIZE, b' ');
+        bytes.extend_from_slice(&record);
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operator(rotation: [[f64; 3]; 3], translation: [f64; 3]) -> SymmetryOperator {
+        SymmetryOperator {
+            rotation,
+            translation,
+        }
+    }
+
+    #[test]
+    fn parse_format_parse() {
+        let screw = operator(
+            [[1.0, 0.0, 0.0], [0.0, -1.0, 0.0], [0.0, 0.0, -1.0]],
+            [0.5, 0.5, 0.0],
+        );
+        let hexagonal = operator(
+            [[1.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 2.0]],
+            [0.0, 0.0, 0.5],
+        );
+        for &(text, expected, formatted) in &[
+            ("X,Y,Z", SymmetryOperator::identity(), "X,Y,Z"),
+            ("1/2+X, 1/2-Y, -Z", screw, "X+1/2,-Y+1/2,-Z"),
+            ("x-y, x, 2z+0.5", hexagonal, "X-Y,X,2Z+1/2"),
+            (
+                "-X+2/3,-Y+1/3,Z-1/6",
+                operator(
+                    [[-1.0, 0.0, 0.0], [0.0, -1.0, 0.0], [0.0, 0.0, 1.0]],
+                    [2.0 / 3.0, 1.0 / 3.0, -1.0 / 6.0],
+                ),
+                "-X+2/3,-Y+1/3,Z-1/6",
+            ),
+        ] {
+            let parsed: SymmetryOperator = text.parse().unwrap();
+            assert_eq!(parsed, expected);
+            assert_eq!(parsed.to_string(), formatted);
+            assert_eq!(formatted.parse::<SymmetryOperator>().unwrap(), parsed);
+        }
+        assert_eq!(screw.apply([0.25, 0.25, 0.5]), [0.75, 0.25, -0.5]);
+    }
+
+    #[test]
+    fn invalid_operators() {
+        for text in &["X,Y", "X,Y,Z,X", "X,,Z", "X,Y,W", "X,Y,Z+1/0", "X,Y,Z+"] {
+            assert!(text.parse::<SymmetryOperator>().is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn records_of_80_characters() {
+        let operators = [SymmetryOperator::identity(), "-X,Y+1/2,-Z".parse().unwrap()];
+        let bytes = write_records(&operators);
+        assert_eq!(bytes.len(), 2 * RECORD_SIZE);
+        let first = std::str::from_utf8(&bytes[..RECORD_SIZE]).unwrap();
+        assert_eq!(first, format!("{:<80}", "X,Y,Z"));
+        assert_eq!(read_records(&bytes).unwrap(), operators);
+
+        // Several operators of a record separated by `*`
+        let mut bytes = format!("{:<80}", " X,Y,Z * -X,Y+1/2,-Z").into_bytes();
+        assert_eq!(read_records(&bytes).unwrap(), operators);
+        bytes.pop();
+        assert!(read_records(&bytes).is_err());
+    }
+}
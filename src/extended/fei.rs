@@ -0,0 +1,314 @@
+//! Per-section metadata of FEI software (`FEI1`, `FEI2`), e.g. EPU, Tomo and Velox
+//!
+//! The extended header holds one block per section. Each block starts with its size, so that
+//! later revisions of the layout only append fields. The first bitmask marks which of the first
+//! fields hold valid values. Blocks of version 2 (`FEI2`) append the fields from the scan rotation
+//! to the objective aperture name. The blocks are followed by zero padding.
+
+use super::Fields;
+use crate::decoder::ByteOrder;
+use crate::{MrcFormatError, MrcResult};
+
+/// Size of the fields read from a block, which every revision has
+const BLOCK_SIZE: usize = 297;
+
+/// Offset of the fields of version 2 in bytes, the size of a block of version 1
+const FEI2_OFFSET: usize = 768;
+
+/// Size of a block of version 2
+const FEI2_BLOCK_SIZE: usize = 888;
+
+/// Metadata of a single section written by FEI software
+///
+/// Fields not marked as valid in the block are `None`, as are the fields of version 2 in blocks of
+/// earlier versions. Lengths are in meters, angles in degrees.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeiMetadata {
+    /// Revision of the layout of the block
+    pub version: i32,
+    /// Time of the acquisition in days since 1899-12-30 (OLE automation date)
+    pub timestamp: Option<f64>,
+    /// Type of the microscope, e.g. `"Krios"`
+    pub microscope_type: Option<String>,
+    /// Serial number of the microscope
+    pub d_number: Option<String>,
+    /// Software that acquired the section, e.g. `"EPU"` or `"Tomography"`
+    pub application: Option<String>,
+    pub application_version: Option<String>,
+    /// Acceleration voltage in volts
+    pub high_tension: Option<f64>,
+    /// Electron dose in electrons per square meter
+    pub dose: Option<f64>,
+    /// Tilt of the stage around its alpha axis, the tilt angle of tomograms
+    pub alpha_tilt: Option<f64>,
+    pub beta_tilt: Option<f64>,
+    pub stage_x: Option<f64>,
+    pub stage_y: Option<f64>,
+    pub stage_z: Option<f64>,
+    /// Angle of the tilt axis in the image
+    pub tilt_axis_angle: Option<f64>,
+    pub dual_axis_rotation: Option<f64>,
+    pub pixel_size_x: Option<f64>,
+    pub pixel_size_y: Option<f64>,
+    pub defocus: Option<f64>,
+    pub stem_defocus: Option<f64>,
+    pub applied_defocus: Option<f64>,
+    pub instrument_mode: Option<i32>,
+    pub projection_mode: Option<i32>,
+    pub objective_lens_mode: Option<String>,
+    pub high_magnification_mode: Option<String>,
+    pub probe_mode: Option<i32>,
+    pub eftem: Option<bool>,
+    pub magnification: Option<f64>,
+    pub scan_rotation: Option<f64>,
+    pub diffraction_pattern_rotation: Option<f64>,
+    pub image_rotation: Option<f64>,
+    pub scan_mode: Option<i32>,
+    pub acquisition_timestamp: Option<i64>,
+    /// Commercial name of the detector, e.g. `"Falcon 4i"`
+    pub detector_commercial_name: Option<String>,
+    /// Tilt angle at the start of continuous tilt acquisitions
+    pub start_tilt_angle: Option<f64>,
+    pub end_tilt_angle: Option<f64>,
+    pub tilt_per_image: Option<f64>,
+    /// Tilt speed in degrees per second
+    pub tilt_speed: Option<f64>,
+    /// Position of the unscattered beam in diffraction patterns (X, Y) in pixels
+    pub beam_center: Option<[i32; 2]>,
+    pub cfeg_flash_timestamp: Option<i64>,
+    pub phase_plate_position_index: Option<i32>,
+    pub objective_aperture_name: Option<String>,
+}
+
+impl FeiMetadata {
+    fn read(fields: Fields, size: usize) -> FeiMetadata {
+        let version = fields.i32(4);
+        let valid = fields.u32(8);
+        let bit = |n: u32| valid & (1 << n) != 0;
+        let f64_at = |n, offset| bit(n).then(|| fields.f64(offset));
+        let i32_at = |n, offset| bit(n).then(|| fields.i32(offset));
+        let string_at = |n, offset| bit(n).then(|| fields.string(offset, 16));
+        let fei2 = version >= 2 && size >= FEI2_BLOCK_SIZE;
+        let at = |offset| FEI2_OFFSET + offset;
+
+        FeiMetadata {
+            version,
+            timestamp: f64_at(0, 12),
+            microscope_type: string_at(1, 20),
+            d_number: string_at(2, 36),
+            application: string_at(3, 52),
+            application_version: string_at(4, 68),
+            high_tension: f64_at(5, 84),
+            dose: f64_at(6, 92),
+            alpha_tilt: f64_at(7, 100),
+            beta_tilt: f64_at(8, 108),
+            stage_x: f64_at(9, 116),
+            stage_y: f64_at(10, 124),
+            stage_z: f64_at(11, 132),
+            tilt_axis_angle: f64_at(12, 140),
+            dual_axis_rotation: f64_at(13, 148),
+            pixel_size_x: f64_at(14, 156),
+            pixel_size_y: f64_at(15, 164),
+            // Bits 16 to 21 mark the unused range at 172-219
+            defocus: f64_at(22, 220),
+            stem_defocus: f64_at(23, 228),
+            applied_defocus: f64_at(24, 236),
+            instrument_mode: i32_at(25, 244),
+            projection_mode: i32_at(26, 248),
+            objective_lens_mode: string_at(27, 252),
+            high_magnification_mode: string_at(28, 268),
+            probe_mode: i32_at(29, 284),
+            eftem: bit(30).then(|| fields.u8(288) != 0),
+            magnification: f64_at(31, 289),
+            scan_rotation: fei2.then(|| fields.f64(at(0))),
+            diffraction_pattern_rotation: fei2.then(|| fields.f64(at(8))),
+            image_rotation: fei2.then(|| fields.f64(at(16))),
+            scan_mode: fei2.then(|| fields.i32(at(24))),
+            acquisition_timestamp: fei2.then(|| fields.i64(at(28))),
+            detector_commercial_name: fei2.then(|| fields.string(at(36), 16)),
+            start_tilt_angle: fei2.then(|| fields.f64(at(52))),
+            end_tilt_angle: fei2.then(|| fields.f64(at(60))),
+            tilt_per_image: fei2.then(|| fields.f64(at(68))),
+            tilt_speed: fei2.then(|| fields.f64(at(76))),
+            beam_center: fei2.then(|| [fields.i32(at(84)), fields.i32(at(88))]),
+            cfeg_flash_timestamp: fei2.then(|| fields.i64(at(92))),
+            phase_plate_position_index: fei2.then(|| fields.i32(at(100))),
+            objective_aperture_name: fei2.then(|| fields.string(at(104), 16)),
+        }
+    }
+}
+
+/// Reads the blocks of up to `sections` sections from the extended header `bytes`
+///
+/// Reading stops at the first block of size 0, which starts the padding.
+pub(crate) fn read_blocks(
+    bytes: &[u8],
+    byte_order: ByteOrder,
+    sections: usize,
+) -> MrcResult<Vec<FeiMetadata>> {
+    let mut blocks = Vec::new();
+    let mut offset = 0;
+    while blocks.len() < sections && offset + BLOCK_SIZE <= bytes.len() {
+        let fields = Fields::new(&bytes[offset..], byte_order);
+        let size = fields.i32(0);
+        if size == 0 {
+            break;
+        }
+        if size < BLOCK_SIZE as i32 || offset + size as usize > bytes.len() {
+            return Err(MrcFormatError::Format(format!(
+                "FEI metadata block of section {} has the invalid size {}",
+                blocks.len(),
+                size
+            ))
+            .into());
+        }
+        blocks.push(FeiMetadata::read(fields, size as usize));
+        offset += size as usize;
+    }
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Block of `size` bytes in `byte_order` with fields set at their offsets
+    struct Block {
+        bytes: Vec<u8>,
+        byte_order: ByteOrder,
+    }
+
+    impl Block {
+        fn new(size: usize, version: i32, valid: u32, byte_order: ByteOrder) -> Block {
+            let mut block = Block {
+                bytes: vec![0; size],
+                byte_order,
+            };
+            block.put(0, &(size as i32).to_ne_bytes());
+            block.put(4, &version.to_ne_bytes());
+            block.put(8, &valid.to_ne_bytes());
+            block
+        }
+
+        fn put(&mut self, offset: usize, ne_bytes: &[u8]) {
+            let field = &mut self.bytes[offset..offset + ne_bytes.len()];
+            field.copy_from_slice(ne_bytes);
+            if self.byte_order != ByteOrder::native() {
+                field.reverse();
+            }
+        }
+
+        fn f64(&mut self, offset: usize, value: f64) -> &mut Block {
+            self.put(offset, &value.to_ne_bytes());
+            self
+        }
+
+        fn string(&mut self, offset: usize, value: &str) -> &mut Block {
+            self.bytes[offset..offset + value.len()].copy_from_slice(value.as_bytes());
+            self
+        }
+    }
+
+    /// Valid bits of the microscope type, the dose, the alpha tilt, the pixel size and the defocus
+    const VALID: u32 = 1 << 1 | 1 << 6 | 1 << 7 | 1 << 14 | 1 << 15 | 1 << 22;
+
+    fn fei1_block(byte_order: ByteOrder) -> Block {
+        let mut block = Block::new(768, 1, VALID, byte_order);
+        block
+            .string(20, "Krios")
+            .f64(92, 2.5e20)
+            .f64(100, -42.0)
+            .f64(108, 7.0)
+            .f64(156, 1.1e-10)
+            .f64(164, 1.2e-10)
+            .f64(220, -2.0e-6);
+        block
+    }
+
+    fn fei2_block(byte_order: ByteOrder) -> Block {
+        let mut block = Block::new(FEI2_BLOCK_SIZE, 2, VALID, byte_order);
+        block
+            .string(20, "Titan")
+            .f64(92, 1.0e20)
+            .f64(100, 30.0)
+            .f64(156, 0.8e-10)
+            .f64(164, 0.8e-10)
+            .f64(220, -1.5e-6)
+            .f64(768, 12.5)
+            .string(804, "Falcon 4i")
+            .f64(820, -60.0)
+            .f64(828, 60.0)
+            .f64(836, 3.0)
+            .f64(844, 1.5)
+            .string(872, "OBJ 100");
+        block.put(792, &3i32.to_ne_bytes());
+        block.put(796, &1_700_000_000i64.to_ne_bytes());
+        block.put(852, &2048i32.to_ne_bytes());
+        block.put(856, &1024i32.to_ne_bytes());
+        block.put(868, &4i32.to_ne_bytes());
+        block
+    }
+
+    #[test]
+    fn blocks_of_both_versions() {
+        for &byte_order in &[ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            let mut bytes = fei1_block(byte_order).bytes;
+            bytes.extend(fei2_block(byte_order).bytes);
+            let blocks = read_blocks(&bytes, byte_order, 2).unwrap();
+            assert_eq!(blocks.len(), 2);
+
+            let fei1 = &blocks[0];
+            assert_eq!(fei1.version, 1);
+            assert_eq!(fei1.microscope_type.as_deref(), Some("Krios"));
+            assert_eq!(fei1.dose, Some(2.5e20));
+            assert_eq!(fei1.alpha_tilt, Some(-42.0));
+            assert_eq!(fei1.pixel_size_x, Some(1.1e-10));
+            assert_eq!(fei1.pixel_size_y, Some(1.2e-10));
+            assert_eq!(fei1.defocus, Some(-2.0e-6));
+            // Not marked as valid, or not part of version 1
+            assert_eq!(fei1.beta_tilt, None);
+            assert_eq!(fei1.timestamp, None);
+            assert_eq!(fei1.scan_rotation, None);
+            assert_eq!(fei1.detector_commercial_name, None);
+
+            let fei2 = &blocks[1];
+            assert_eq!(fei2.version, 2);
+            assert_eq!(fei2.microscope_type.as_deref(), Some("Titan"));
+            assert_eq!(fei2.dose, Some(1.0e20));
+            assert_eq!(fei2.alpha_tilt, Some(30.0));
+            assert_eq!(fei2.pixel_size_x, Some(0.8e-10));
+            assert_eq!(fei2.defocus, Some(-1.5e-6));
+            assert_eq!(fei2.scan_rotation, Some(12.5));
+            assert_eq!(fei2.scan_mode, Some(3));
+            assert_eq!(fei2.acquisition_timestamp, Some(1_700_000_000));
+            assert_eq!(fei2.detector_commercial_name.as_deref(), Some("Falcon 4i"));
+            assert_eq!(fei2.start_tilt_angle, Some(-60.0));
+            assert_eq!(fei2.end_tilt_angle, Some(60.0));
+            assert_eq!(fei2.tilt_per_image, Some(3.0));
+            assert_eq!(fei2.tilt_speed, Some(1.5));
+            assert_eq!(fei2.beam_center, Some([2048, 1024]));
+            assert_eq!(fei2.phase_plate_position_index, Some(4));
+            assert_eq!(fei2.objective_aperture_name.as_deref(), Some("OBJ 100"));
+        }
+    }
+
+    #[test]
+    fn blocks_end_at_the_padding() {
+        let byte_order = ByteOrder::LittleEndian;
+        let mut bytes = fei1_block(byte_order).bytes;
+        bytes.extend(vec![0; 768]);
+        bytes.extend(fei1_block(byte_order).bytes);
+        let blocks = read_blocks(&bytes, byte_order, 3).unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].alpha_tilt, Some(-42.0));
+
+        // Fewer sections than blocks
+        let mut bytes = fei1_block(byte_order).bytes;
+        bytes.extend(fei2_block(byte_order).bytes);
+        assert_eq!(read_blocks(&bytes, byte_order, 1).unwrap().len(), 1);
+
+        // A block reaching past the extended header
+        let bytes = fei2_block(byte_order).bytes;
+        assert!(read_blocks(&bytes[..800], byte_order, 1).is_err());
+    }
+}
@@ -0,0 +1,234 @@
+//! Layout of files written by Priism (IVE) and DeltaVision software (`AGAR`)
+//!
+//! Priism reuses parts of the main header for the wavelengths and timepoints of light microscopy
+//! data. The sections of all focal planes, wavelengths (channels) and timepoints are interleaved
+//! in `nz` in the order given by the image sequence.
+//!
+//! The extended header holds `nint` integers followed by `nreal` floats per section. DeltaVision
+//! files store, among others, the elapsed time, the stage position, the exposure time and the
+//! excitation and emission wavelengths in the floats.
+
+use super::Fields;
+use crate::decoder::ByteOrder;
+use crate::{MrcFormatError, MrcResult};
+
+/// Offset of the number of timepoints in bytes
+const NUM_TIMES_OFFSET: usize = 180;
+
+/// Offset of the image sequence in bytes
+const IMAGE_SEQUENCE_OFFSET: usize = 182;
+
+/// Offset of the number of wavelengths in bytes, followed by up to 5 wavelengths
+const NUM_WAVES_OFFSET: usize = 196;
+
+/// Offset of the origin in bytes, in the order Z, X, Y
+const ORIGIN_OFFSET: usize = 208;
+
+/// Maximum number of wavelengths stored in the header
+const MAX_WAVES: usize = 5;
+
+/// Order of the focal planes (Z), wavelengths (W) and timepoints (T) in the sections, from the
+/// fastest to the slowest changing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImageSequence {
+    ZTW,
+    WZT,
+    ZWT,
+}
+
+/// Priism layout of a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct IveHeader {
+    pub image_sequence: ImageSequence,
+    /// Number of focal planes of each channel at each timepoint
+    pub focal_planes: usize,
+    pub channels: usize,
+    pub timepoints: usize,
+    /// Wavelength of each channel in nanometers, the header holds at most 5
+    pub wavelengths: Vec<i16>,
+    /// Origin (X, Y, Z) in microns
+    pub origin: [f32; 3],
+}
+
+impl IveHeader {
+    /// Index of the section of focal plane `z` of `channel` at `timepoint`
+    pub fn section_index(&self, z: usize, channel: usize, timepoint: usize) -> Option<usize> {
+        if z >= self.focal_planes || channel >= self.channels || timepoint >= self.timepoints {
+            return None;
+        }
+        let (nz, nw, nt) = (self.focal_planes, self.channels, self.timepoints);
+        Some(match self.image_sequence {
+            ImageSequence::ZTW => z + nz * (timepoint + nt * channel),
+            ImageSequence::WZT => channel + nw * (z + nz * timepoint),
+            ImageSequence::ZWT => z + nz * (channel + nw * timepoint),
+        })
+    }
+
+    /// Focal plane, channel and timepoint of section `index`
+    pub fn section_coordinates(&self, index: usize) -> Option<[usize; 3]> {
+        let (nz, nw, nt) = (self.focal_planes, self.channels, self.timepoints);
+        if index >= nz * nw * nt {
+            return None;
+        }
+        Some(match self.image_sequence {
+            ImageSequence::ZTW => [index % nz, index / (nz * nt), index / nz % nt],
+            ImageSequence::WZT => [index / nw % nz, index % nw, index / (nw * nz)],
+            ImageSequence::ZWT => [index % nz, index / nz % nw, index / (nz * nw)],
+        })
+    }
+}
+
+/// Integers and floats of a single section
+#[derive(Debug, Clone, PartialEq)]
+pub struct IveSection {
+    pub ints: Vec<i32>,
+    pub floats: Vec<f32>,
+}
+
+/// Reads the Priism layout of a volume of `sections` sections from the main header `bytes`
+pub(crate) fn read_header(
+    bytes: &[u8],
+    byte_order: ByteOrder,
+    sections: usize,
+) -> MrcResult<IveHeader> {
+    let fields = Fields::new(bytes, byte_order);
+    let image_sequence = match fields.i16(IMAGE_SEQUENCE_OFFSET) {
+        0 => ImageSequence::ZTW,
+        1 => ImageSequence::WZT,
+        2 => ImageSequence::ZWT,
+        sequence => {
+            return Err(
+                MrcFormatError::Format(format!("invalid image sequence {}", sequence)).into(),
+            )
+        }
+    };
+    // Files of a single channel or timepoint may leave the counts at 0
+    let timepoints = fields.i16(NUM_TIMES_OFFSET).max(1) as usize;
+    let channels = fields.i16(NUM_WAVES_OFFSET).max(1) as usize;
+    let wavelengths = (0..channels.min(MAX_WAVES))
+        .map(|i| fields.i16(NUM_WAVES_OFFSET + 2 + 2 * i))
+        .collect();
+    if !sections.is_multiple_of(channels * timepoints) {
+        return Err(MrcFormatError::Format(format!(
+            "{} sections are not divisible into {} channels at {} timepoints",
+            sections, channels, timepoints
+        ))
+        .into());
+    }
+
+    Ok(IveHeader {
+        image_sequence,
+        focal_planes: sections / (channels * timepoints),
+        channels,
+        timepoints,
+        wavelengths,
+        origin: [
+            fields.f32(ORIGIN_OFFSET + 4),
+            fields.f32(ORIGIN_OFFSET + 8),
+            fields.f32(ORIGIN_OFFSET),
+        ],
+    })
+}
+
+/// Reads the records of up to `sections` sections from the extended header `bytes`
+///
+/// Each record holds `nint` integers followed by `nreal` floats.
+pub(crate) fn read_sections(
+    bytes: &[u8],
+    byte_order: ByteOrder,
+    sections: usize,
+    nint: i16,
+    nreal: i16,
+) -> MrcResult<Vec<IveSection>> {
+    if nint < 0 || nreal < 0 {
+        return Err(MrcFormatError::Format(format!(
+            "negative number of integers {} or floats {} per section",
+            nint, nreal
+        ))
+        .into());
+    }
+    let (nint, nreal) = (nint as usize, nreal as usize);
+    let size = 4 * (nint + nreal);
+    if size == 0 {
+        return Ok(Vec::new());
+    }
+
+    Ok(bytes
+        .chunks_exact(size)
+        .take(sections)
+        .map(|record| {
+            let fields = Fields::new(record, byte_order);
+            IveSection {
+                ints: (0..nint).map(|i| fields.i32(4 * i)).collect(),
+                floats: (0..nreal).map(|i| fields.f32(4 * (nint + i))).collect(),
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Main header of 2 channels at 3 timepoints with the given image sequence, little endian
+    fn header_bytes(sequence: i16) -> Vec<u8> {
+        let mut bytes = vec![0u8; 1024];
+        bytes[NUM_TIMES_OFFSET..NUM_TIMES_OFFSET + 2].copy_from_slice(&3i16.to_le_bytes());
+        bytes[IMAGE_SEQUENCE_OFFSET..IMAGE_SEQUENCE_OFFSET + 2]
+            .copy_from_slice(&sequence.to_le_bytes());
+        for (i, value) in [2i16, 488, 561].iter().enumerate() {
+            let offset = NUM_WAVES_OFFSET + 2 * i;
+            bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+        }
+        for (i, value) in [3.0f32, 1.0, 2.0].iter().enumerate() {
+            let offset = ORIGIN_OFFSET + 4 * i;
+            bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn interleaving_of_each_image_sequence() {
+        let (nz, nw, nt) = (4, 2, 3);
+        for (code, sequence) in [ImageSequence::ZTW, ImageSequence::WZT, ImageSequence::ZWT]
+            .iter()
+            .enumerate()
+        {
+            let header =
+                read_header(&header_bytes(code as i16), ByteOrder::LittleEndian, 24).unwrap();
+            assert_eq!(header.image_sequence, *sequence);
+            assert_eq!(
+                (header.focal_planes, header.channels, header.timepoints),
+                (nz, nw, nt)
+            );
+            assert_eq!(header.wavelengths, [488, 561]);
+            assert_eq!(header.origin, [1.0, 2.0, 3.0]);
+
+            // Sections in the order of the file, from the slowest to the fastest changing axis
+            let mut order = Vec::new();
+            for a in 0..[nw, nt, nt][code] {
+                for b in 0..[nt, nz, nw][code] {
+                    for c in 0..[nz, nw, nz][code] {
+                        order.push(match sequence {
+                            ImageSequence::ZTW => [c, a, b],
+                            ImageSequence::WZT => [b, c, a],
+                            ImageSequence::ZWT => [c, b, a],
+                        });
+                    }
+                }
+            }
+            for (index, &[z, w, t]) in order.iter().enumerate() {
+                assert_eq!(header.section_index(z, w, t), Some(index));
+                assert_eq!(header.section_coordinates(index), Some([z, w, t]));
+            }
+            assert_eq!(header.section_index(nz, 0, 0), None);
+            assert_eq!(header.section_coordinates(24), None);
+        }
+    }
+
+    #[test]
+    fn invalid_layouts_are_rejected() {
+        assert!(read_header(&header_bytes(0), ByteOrder::LittleEndian, 10).is_err());
+        assert!(read_header(&header_bytes(3), ByteOrder::LittleEndian, 24).is_err());
+    }
+}
@@ -0,0 +1,69 @@
+//! Typed access to the metadata in the extended header
+//!
+//! The layout of the extended header is given by its type (`EXTTYP`), see `ExtendedHeaderType`.
+//! The raw bytes are read by `Decoder::read_extended_header` and the typed metadata by the
+//! decoder method of the respective type.
+
+use crate::decoder::ByteOrder;
+
+pub mod ccp4;
+pub mod fei;
+pub mod ive;
+pub mod seri;
+
+/// Fixed size fields of a block of the extended header
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Fields<'a> {
+    bytes: &'a [u8],
+    byte_order: ByteOrder,
+}
+
+impl<'a> Fields<'a> {
+    pub(crate) fn new(bytes: &'a [u8], byte_order: ByteOrder) -> Fields<'a> {
+        Fields { bytes, byte_order }
+    }
+
+    fn word<const N: usize>(&self, offset: usize) -> [u8; N] {
+        let mut word = [0u8; N];
+        word.copy_from_slice(&self.bytes[offset..offset + N]);
+        if self.byte_order != ByteOrder::native() {
+            word.reverse();
+        }
+        word
+    }
+
+    pub(crate) fn u8(&self, offset: usize) -> u8 {
+        self.bytes[offset]
+    }
+
+    pub(crate) fn i16(&self, offset: usize) -> i16 {
+        i16::from_ne_bytes(self.word(offset))
+    }
+
+    pub(crate) fn i32(&self, offset: usize) -> i32 {
+        i32::from_ne_bytes(self.word(offset))
+    }
+
+    pub(crate) fn i64(&self, offset: usize) -> i64 {
+        i64::from_ne_bytes(self.word(offset))
+    }
+
+    pub(crate) fn u32(&self, offset: usize) -> u32 {
+        u32::from_ne_bytes(self.word(offset))
+    }
+
+    pub(crate) fn f32(&self, offset: usize) -> f32 {
+        f32::from_ne_bytes(self.word(offset))
+    }
+
+    pub(crate) fn f64(&self, offset: usize) -> f64 {
+        f64::from_ne_bytes(self.word(offset))
+    }
+
+    /// Character field of `len` bytes, stripped of NUL padding and surrounding whitespace
+    pub(crate) fn string(&self, offset: usize, len: usize) -> String {
+        let field = &self.bytes[offset..offset + len];
+        let end = field.iter().position(|&c| c == 0).unwrap_or(len);
+        String::from_utf8_lossy(&field[..end]).trim().to_string()
+    }
+}
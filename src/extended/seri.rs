@@ -0,0 +1,220 @@
+//! Per-section records of SerialEM (`SERI`)
+//!
+//! The extended header holds one record of `nint` bytes per section. `nreal` holds bit flags of
+//! the fields present in the records, which follow each other in the order of their bits:
+//!
+//! | Bit  | Field                                   | Bytes |
+//! |------|-----------------------------------------|-------|
+//! | 1    | Tilt angle in degrees * 100             | 2     |
+//! | 2    | X, Y, Z piece coordinates of montages   | 6     |
+//! | 4    | X, Y stage position in microns * 25     | 4     |
+//! | 8    | Magnification / 100                     | 2     |
+//! | 16   | Intensity * 25000                       | 2     |
+//! | 32   | Exposure dose in electrons per square Å | 4     |
+//! | 64   | Reserved                                | 2     |
+//! | 128  | Reserved                                | 4     |
+//! | 256  | Reserved                                | 2     |
+//! | 512  | Reserved                                | 4     |
+//! | 1024 | Reserved                                | 2     |
+//!
+//! Details in the IMOD documentation of the MRC format.
+
+use super::Fields;
+use crate::decoder::ByteOrder;
+use crate::{MrcFormatError, MrcResult};
+
+/// Bytes of the fields of each flag, from the lowest bit
+const FIELD_SIZES: [usize; 11] = [2, 6, 4, 2, 2, 4, 2, 4, 2, 4, 2];
+
+const TILT_ANGLE: i16 = 1;
+const PIECE_COORDINATES: i16 = 2;
+const STAGE_POSITION: i16 = 4;
+const MAGNIFICATION: i16 = 8;
+const INTENSITY: i16 = 16;
+const DOSE: i16 = 32;
+
+/// Record of a single section written by SerialEM
+///
+/// Fields not present in the records of the file are `None`. The values are scaled back from
+/// their stored integer representation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SerialEmRecord {
+    /// Tilt angle in degrees
+    pub tilt_angle: Option<f32>,
+    /// Position of the piece in the montage (X, Y, Z)
+    pub piece_coordinates: Option<[i16; 3]>,
+    /// Position of the stage (X, Y) in microns, SerialEM does not record Z
+    pub stage_position: Option<[f32; 2]>,
+    pub magnification: Option<i32>,
+    /// Intensity (C2) setting of the illumination
+    pub intensity: Option<f32>,
+    /// Exposure dose in electrons per square Å
+    pub dose: Option<f32>,
+}
+
+impl SerialEmRecord {
+    fn read(fields: Fields, flags: i16) -> SerialEmRecord {
+        let mut offsets = [0; FIELD_SIZES.len()];
+        let mut offset = 0;
+        for (bit, &size) in FIELD_SIZES.iter().enumerate() {
+            offsets[bit] = offset;
+            if flags & (1 << bit) != 0 {
+                offset += size;
+            }
+        }
+        let field = |flag: i16| {
+            if flags & flag != 0 {
+                Some(offsets[flag.trailing_zeros() as usize])
+            } else {
+                None
+            }
+        };
+
+        SerialEmRecord {
+            tilt_angle: field(TILT_ANGLE).map(|at| f32::from(fields.i16(at)) / 100.0),
+            piece_coordinates: field(PIECE_COORDINATES)
+                .map(|at| [fields.i16(at), fields.i16(at + 2), fields.i16(at + 4)]),
+            stage_position: field(STAGE_POSITION).map(|at| {
+                [
+                    f32::from(fields.i16(at)) / 25.0,
+                    f32::from(fields.i16(at + 2)) / 25.0,
+                ]
+            }),
+            magnification: field(MAGNIFICATION).map(|at| i32::from(fields.i16(at)) * 100),
+            intensity: field(INTENSITY).map(|at| f32::from(fields.i16(at)) / 25000.0),
+            dose: field(DOSE).map(|at| short_float(fields.i16(at), fields.i16(at + 2))),
+        }
+    }
+}
+
+/// Decodes the float SerialEM stores in two integers
+///
+/// The first holds the sign and the upper bits of the mantissa, the second the lower 8 bits of the
+/// mantissa and, in its upper bits, the signed power of 2.
+fn short_float(high: i16, low: i16) -> f32 {
+    let mantissa = f32::from(high.unsigned_abs()) * 256.0 + f32::from(low.unsigned_abs() % 256);
+    let exponent = i32::from(low.unsigned_abs() / 256) * i32::from(low.signum() | 1);
+    mantissa * 2f32.powi(exponent) * f32::from(high.signum() | 1)
+}
+
+/// Whether records of `nint` bytes hold exactly the fields of the flags `nreal`
+pub(crate) fn matches_flags(nint: i16, nreal: i16) -> bool {
+    let flags_size: usize = FIELD_SIZES
+        .iter()
+        .enumerate()
+        .filter(|&(bit, _)| nreal & (1 << bit) != 0)
+        .map(|(_, &size)| size)
+        .sum();
+    nint > 0 && nreal >> FIELD_SIZES.len() == 0 && flags_size == nint as usize
+}
+
+/// Reads the records of up to `sections` sections from the extended header `bytes`
+///
+/// `nint` is the size of a record and `nreal` the flags of its fields.
+pub(crate) fn read_records(
+    bytes: &[u8],
+    byte_order: ByteOrder,
+    sections: usize,
+    nint: i16,
+    nreal: i16,
+) -> MrcResult<Vec<SerialEmRecord>> {
+    // Otherwise the header holds `nint` integers and `nreal` reals per section as in Agard files
+    if !matches_flags(nint, nreal) {
+        return Err(MrcFormatError::Format(format!(
+            "SerialEM records of {} bytes do not match the flags {}",
+            nint, nreal
+        ))
+        .into());
+    }
+
+    Ok(bytes
+        .chunks_exact(nint as usize)
+        .take(sections)
+        .map(|record| SerialEmRecord::read(Fields::new(record, byte_order), nreal))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_FIELDS: i16 =
+        TILT_ANGLE | PIECE_COORDINATES | STAGE_POSITION | MAGNIFICATION | INTENSITY | DOSE;
+
+    fn record(words: &[i16], byte_order: ByteOrder) -> Vec<u8> {
+        words
+            .iter()
+            .flat_map(|word| match byte_order {
+                ByteOrder::LittleEndian => word.to_le_bytes(),
+                ByteOrder::BigEndian => word.to_be_bytes(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn record_size_of_the_flags() {
+        assert!(matches_flags(20, ALL_FIELDS));
+        assert!(matches_flags(6, TILT_ANGLE | DOSE));
+        assert!(matches_flags(34, 2047));
+        assert!(!matches_flags(18, ALL_FIELDS));
+        assert!(!matches_flags(22, ALL_FIELDS));
+        assert!(!matches_flags(0, 0));
+        assert!(!matches_flags(4, 2048 | DOSE));
+    }
+
+    #[test]
+    fn records_of_all_fields() {
+        // Dose of 192 * 2^-8 and 1 * 2^8
+        let words = [
+            [4550, 1, 2, 3, 250, -500, 500, 12500, 0, -2240],
+            [-1025, 4, 5, 6, -25, 50, 100, 2500, 1, 0],
+        ];
+        for &byte_order in &[ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            let mut bytes: Vec<u8> = words.iter().flat_map(|w| record(w, byte_order)).collect();
+            assert_eq!(bytes.len(), 2 * 20);
+            // Records of sections beyond `nz` are ignored
+            bytes.extend(vec![0; 20]);
+            let records = read_records(&bytes, byte_order, 2, 20, ALL_FIELDS).unwrap();
+            assert_eq!(
+                records,
+                [
+                    SerialEmRecord {
+                        tilt_angle: Some(45.5),
+                        piece_coordinates: Some([1, 2, 3]),
+                        stage_position: Some([10.0, -20.0]),
+                        magnification: Some(50000),
+                        intensity: Some(0.5),
+                        dose: Some(0.75),
+                    },
+                    SerialEmRecord {
+                        tilt_angle: Some(-10.25),
+                        piece_coordinates: Some([4, 5, 6]),
+                        stage_position: Some([-1.0, 2.0]),
+                        magnification: Some(10000),
+                        intensity: Some(0.1),
+                        dose: Some(256.0),
+                    },
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn records_of_some_fields() {
+        let bytes = record(&[-3000, 0, 7], ByteOrder::LittleEndian);
+        let records =
+            read_records(&bytes, ByteOrder::LittleEndian, 1, 6, TILT_ANGLE | DOSE).unwrap();
+        assert_eq!(
+            records,
+            [SerialEmRecord {
+                tilt_angle: Some(-30.0),
+                piece_coordinates: None,
+                stage_position: None,
+                magnification: None,
+                intensity: None,
+                dose: Some(7.0),
+            }]
+        );
+        assert!(read_records(&bytes, ByteOrder::LittleEndian, 1, 4, TILT_ANGLE | DOSE).is_err());
+    }
+}
@@ -0,0 +1,235 @@
+//! Decoding of sections through the `image` crate
+//!
+//! Each section of a volume is a 2D image. The modes map to color types as follows:
+//!
+//! * `Mode0` and `Mode101` are `L8`, signed bytes are offset by 128
+//! * `Mode1` and `Mode6` are `L16`, signed words are offset by 32768
+//! * `Mode2` and `Mode12` are `Rgb32F` with equal channels, as `image` has no single channel float
+//!   color type
+//! * `Mode16` is `Rgb8`
+//!
+//! Complex modes have no color type and are not supported.
+//!
+//! Requires the `image` feature.
+
+use crate::decoder::{Decoder, DecodingResult};
+use crate::{Mode, MrcError, MrcResult, MrcUnsupportedError, MrcUsageError};
+use ::image::error::{
+    DecodingError, ImageFormatHint, LimitError, LimitErrorKind, UnsupportedError,
+    UnsupportedErrorKind,
+};
+use ::image::hooks::{register_decoding_hook, GenericReader};
+use ::image::{ColorType, ImageDecoder, ImageError, ImageResult};
+use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::io::{Read, Seek};
+
+/// File extensions MRC files commonly use
+pub const EXTENSIONS: [&str; 6] = ["mrc", "mrcs", "map", "st", "ali", "rec"];
+
+/// Decoder of a single section of a MRC file as an image
+#[derive(Debug)]
+pub struct MrcImageDecoder<R>
+where
+    R: Read + Seek,
+{
+    decoder: Decoder<R>,
+    section: usize,
+    color_type: ColorType,
+}
+
+impl<R: Read + Seek> MrcImageDecoder<R> {
+    /// Decoder of the first section, which is the whole image of single section files
+    pub fn new(reader: R) -> MrcResult<MrcImageDecoder<R>> {
+        MrcImageDecoder::new_section(reader, 0)
+    }
+
+    /// Decoder of section `z` of a stack
+    pub fn new_section(reader: R, z: usize) -> MrcResult<MrcImageDecoder<R>> {
+        let decoder = Decoder::new(reader)?;
+        let sections = decoder.section_count()?;
+        if z >= sections {
+            return Err(MrcUsageError::SectionOutOfBounds {
+                section: z,
+                sections,
+            }
+            .into());
+        }
+        let mode = decoder.header()?.data_mode()?;
+        let color_type = match mode {
+            Mode::Mode0 | Mode::Mode101 => ColorType::L8,
+            Mode::Mode1 | Mode::Mode6 => ColorType::L16,
+            Mode::Mode2 | Mode::Mode12 => ColorType::Rgb32F,
+            Mode::Mode16 => ColorType::Rgb8,
+            mode => return Err(MrcUnsupportedError::UnsupportedMode(mode).into()),
+        };
+        Ok(MrcImageDecoder {
+            decoder,
+            section: z,
+            color_type,
+        })
+    }
+
+    /// Number of sections of the file
+    pub fn section_count(&self) -> MrcResult<usize> {
+        self.decoder.section_count()
+    }
+}
+
+impl<R: Read + Seek> ImageDecoder for MrcImageDecoder<R> {
+    fn dimensions(&self) -> (u32, u32) {
+        self.decoder.dimensions().unwrap_or((0, 0))
+    }
+
+    fn color_type(&self) -> ColorType {
+        self.color_type
+    }
+
+    fn read_image(mut self, buf: &mut [u8]) -> ImageResult<()> {
+        assert_eq!(u64::try_from(buf.len()), Ok(self.total_bytes()));
+        match self.decoder.read_section(self.section)? {
+            DecodingResult::U8(data) => buf.copy_from_slice(&data),
+            DecodingResult::I8(data) => {
+                for (byte, n) in buf.iter_mut().zip(data) {
+                    *byte = n as u8 ^ 0x80;
+                }
+            }
+            DecodingResult::U16(data) => {
+                for (bytes, n) in buf.chunks_exact_mut(2).zip(data) {
+                    bytes.copy_from_slice(&n.to_ne_bytes());
+                }
+            }
+            DecodingResult::I16(data) => {
+                for (bytes, n) in buf.chunks_exact_mut(2).zip(data) {
+                    bytes.copy_from_slice(&(n as u16 ^ 0x8000).to_ne_bytes());
+                }
+            }
+            DecodingResult::F32(data) => {
+                for (pixel, n) in buf.chunks_exact_mut(12).zip(data) {
+                    for channel in pixel.chunks_exact_mut(4) {
+                        channel.copy_from_slice(&n.to_ne_bytes());
+                    }
+                }
+            }
+            _ => return Err(MrcError::from(MrcUnsupportedError::UnsupportedDataType).into()),
+        }
+        Ok(())
+    }
+
+    fn read_image_boxed(self: Box<Self>, buf: &mut [u8]) -> ImageResult<()> {
+        (*self).read_image(buf)
+    }
+}
+
+/// Registers `MrcImageDecoder` with `image` for the `EXTENSIONS`
+///
+/// Afterwards `image::open` decodes the first section of MRC files. Returns `false` if a decoder
+/// was already registered for any of the extensions.
+pub fn register_decoding_hooks() -> bool {
+    let mut registered = true;
+    for extension in EXTENSIONS.iter() {
+        registered &= register_decoding_hook(
+            OsString::from(extension),
+            Box::new(|reader: GenericReader<'_>| {
+                Ok(Box::new(MrcImageDecoder::new(reader)?) as Box<dyn ImageDecoder>)
+            }),
+        );
+    }
+    registered
+}
+
+impl From<MrcError> for ImageError {
+    fn from(err: MrcError) -> ImageError {
+        let format = || ImageFormatHint::Name("MRC".to_string());
+        match err {
+            MrcError::IoError(err) => ImageError::IoError(err),
+            MrcError::LimitsExceeded => {
+                ImageError::Limits(LimitError::from_kind(LimitErrorKind::InsufficientMemory))
+            }
+            MrcError::UnsupportedError(err) => {
+                ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+                    format(),
+                    UnsupportedErrorKind::GenericFeature(err.to_string()),
+                ))
+            }
+            err => ImageError::Decoding(DecodingError::new(format(), err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::{Encoder, EncodingBuffer};
+    use std::io::Cursor;
+
+    /// 2 x 1 x 2 volume of `data` in `mode`
+    fn encode(mode: Mode, data: EncodingBuffer) -> Cursor<Vec<u8>> {
+        let mut encoder = Encoder::new(Cursor::new(Vec::new()));
+        encoder.write_volume(mode, [2, 1, 2], data).unwrap();
+        Cursor::new(encoder.into_inner().into_inner())
+    }
+
+    /// Pixels of the second section decoded as an image of `color_type`
+    fn second_section(reader: Cursor<Vec<u8>>, color_type: ColorType) -> Vec<u8> {
+        let decoder = MrcImageDecoder::new_section(reader, 1).unwrap();
+        assert_eq!(decoder.dimensions(), (2, 1));
+        assert_eq!(decoder.color_type(), color_type);
+        let mut buf = vec![0; decoder.total_bytes() as usize];
+        decoder.read_image(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn color_types_of_the_modes() {
+        let signed = encode(Mode::Mode0, EncodingBuffer::I8(&[0, 0, -128, 5]));
+        assert_eq!(second_section(signed, ColorType::L8), [0, 133]);
+        let unsigned = encode(Mode::Mode0, EncodingBuffer::U8(&[0, 0, 200, 5]));
+        assert_eq!(second_section(unsigned, ColorType::L8), [200, 5]);
+        let packed = encode(Mode::Mode101, EncodingBuffer::U8(&[0, 0, 15, 7]));
+        assert_eq!(second_section(packed, ColorType::L8), [15, 7]);
+
+        let words = encode(Mode::Mode1, EncodingBuffer::I16(&[0, 0, -32768, 1]));
+        let expected = [0u16.to_ne_bytes(), 32769u16.to_ne_bytes()].concat();
+        assert_eq!(second_section(words, ColorType::L16), expected);
+        let unsigned = encode(Mode::Mode6, EncodingBuffer::U16(&[0, 0, 65535, 1]));
+        let expected = [65535u16.to_ne_bytes(), 1u16.to_ne_bytes()].concat();
+        assert_eq!(second_section(unsigned, ColorType::L16), expected);
+
+        // `image` has no `L32F`, the value is repeated in the channels of `Rgb32F`
+        let expected: Vec<u8> = [1.5f32, 1.5, 1.5, -2.0, -2.0, -2.0]
+            .iter()
+            .flat_map(|n| n.to_ne_bytes())
+            .collect();
+        let floats = encode(Mode::Mode2, EncodingBuffer::F32(&[0.0, 0.0, 1.5, -2.0]));
+        assert_eq!(second_section(floats, ColorType::Rgb32F), expected);
+        let half = [0, 0, 0x3e00, 0xc000];
+        let halves = encode(Mode::Mode12, EncodingBuffer::F16(&half));
+        assert_eq!(second_section(halves, ColorType::Rgb32F), expected);
+
+        let rgb = encode(
+            Mode::Mode16,
+            EncodingBuffer::U8(&[0, 0, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6]),
+        );
+        assert_eq!(second_section(rgb, ColorType::Rgb8), [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn unsupported_modes_and_sections() {
+        let complex = encode(Mode::Mode4, EncodingBuffer::ComplexF32(&[0.0; 8]));
+        assert!(matches!(
+            MrcImageDecoder::new(complex),
+            Err(MrcError::UnsupportedError(
+                MrcUnsupportedError::UnsupportedMode(Mode::Mode4)
+            ))
+        ));
+        let words = encode(Mode::Mode1, EncodingBuffer::I16(&[0; 4]));
+        assert!(matches!(
+            MrcImageDecoder::new_section(words, 2),
+            Err(MrcError::UsageError(MrcUsageError::SectionOutOfBounds {
+                section: 2,
+                sections: 2
+            }))
+        ));
+    }
+}
@@ -5,10 +5,20 @@
 //! # Related Links
 //! * <https://www.sciencedirect.com/science/article/pii/S104784771500074X> - The MRC specification
 
+use std::convert::TryFrom;
+
+#[cfg(feature = "ndarray")]
+pub mod array;
 mod bytecast;
 pub mod decoder;
+pub mod encoder;
 mod error;
-pub use self::error::{MrcError, MrcResult};
+pub mod extended;
+#[cfg(feature = "image")]
+pub mod image;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+pub use self::error::{MrcError, MrcFormatError, MrcResult, MrcUnsupportedError, MrcUsageError};
 
 /// An enumeration over supported modes
 #[derive(Copy, PartialEq, Eq, Debug, Clone, Hash)]
@@ -31,6 +41,9 @@ pub enum Mode {
     /// Represents a 2-byte unsigned integer [implemented by UCSFtomo (Zheng et al., 2007)]
     Mode6,
 
+    /// Represents a 2-byte IEEE 754 half-precision real [(IMOD)]
+    Mode12,
+
     /// [(Kremer et al., 1996)]
     IMOD,
 
@@ -42,4 +55,141 @@ pub enum Mode {
 
     /// Represents RGB data in 3 1-byte unsigned integers [(IMOD)]
     Mode16,
+
+    /// Represents 4-bit unsigned integers packed two per byte [(IMOD)]
+    Mode101,
+}
+
+impl Mode {
+    /// Number of bits a single voxel occupies in the data block
+    ///
+    /// `None` for the software specific variants, which do not identify a data type by themselves.
+    pub fn bits_per_voxel(self) -> Option<usize> {
+        match self {
+            Mode::Mode0 => Some(8),
+            Mode::Mode1 | Mode::Mode6 | Mode::Mode12 => Some(16),
+            Mode::Mode2 | Mode::Mode3 => Some(32),
+            Mode::Mode4 => Some(64),
+            Mode::Mode16 => Some(24),
+            Mode::Mode101 => Some(4),
+            Mode::IMOD | Mode::EPU | Mode::IVE => None,
+        }
+    }
+
+    /// Number of bytes a single voxel occupies in the data block
+    ///
+    /// `None` for `Mode101`, where two voxels share a byte, and for the software specific variants.
+    pub fn bytes_per_voxel(self) -> Option<usize> {
+        match self.bits_per_voxel() {
+            Some(bits) if bits % 8 == 0 => Some(bits / 8),
+            _ => None,
+        }
+    }
+
+    /// Number of samples a single voxel consists of
+    ///
+    /// Complex voxels consist of a real and an imaginary part, RGB voxels of three channels.
+    pub fn samples_per_voxel(self) -> usize {
+        match self {
+            Mode::Mode3 | Mode::Mode4 => 2,
+            Mode::Mode16 => 3,
+            _ => 1,
+        }
+    }
+
+    /// Whether the voxels are complex numbers
+    pub fn is_complex(self) -> bool {
+        matches!(self, Mode::Mode3 | Mode::Mode4)
+    }
+
+    /// Whether the voxels (or their components) are integers
+    pub fn is_integer(self) -> bool {
+        matches!(
+            self,
+            Mode::Mode0 | Mode::Mode1 | Mode::Mode3 | Mode::Mode6 | Mode::Mode16 | Mode::Mode101
+        )
+    }
+}
+
+impl TryFrom<i32> for Mode {
+    type Error = MrcError;
+
+    /// Maps the `mode` word of the header to a `Mode`
+    fn try_from(code: i32) -> MrcResult<Mode> {
+        match code {
+            0 => Ok(Mode::Mode0),
+            1 => Ok(Mode::Mode1),
+            2 => Ok(Mode::Mode2),
+            3 => Ok(Mode::Mode3),
+            4 => Ok(Mode::Mode4),
+            6 => Ok(Mode::Mode6),
+            12 => Ok(Mode::Mode12),
+            16 => Ok(Mode::Mode16),
+            101 => Ok(Mode::Mode101),
+            other => Err(MrcError::FormatError(MrcFormatError::InvalidMode(other))),
+        }
+    }
+}
+
+impl TryFrom<Mode> for i32 {
+    type Error = MrcError;
+
+    /// Maps a `Mode` to the `mode` word of the header
+    ///
+    /// The software specific variants have no code of their own and are unsupported.
+    fn try_from(mode: Mode) -> MrcResult<i32> {
+        match mode {
+            Mode::Mode0 => Ok(0),
+            Mode::Mode1 => Ok(1),
+            Mode::Mode2 => Ok(2),
+            Mode::Mode3 => Ok(3),
+            Mode::Mode4 => Ok(4),
+            Mode::Mode6 => Ok(6),
+            Mode::Mode12 => Ok(12),
+            Mode::Mode16 => Ok(16),
+            Mode::Mode101 => Ok(101),
+            Mode::IMOD | Mode::EPU | Mode::IVE => Err(MrcError::UnsupportedError(
+                MrcUnsupportedError::UnsupportedMode(mode),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mode_codes_round_trip() {
+        for &(code, mode) in &[
+            (0, Mode::Mode0),
+            (1, Mode::Mode1),
+            (2, Mode::Mode2),
+            (3, Mode::Mode3),
+            (4, Mode::Mode4),
+            (6, Mode::Mode6),
+            (12, Mode::Mode12),
+            (16, Mode::Mode16),
+            (101, Mode::Mode101),
+        ] {
+            assert_eq!(Mode::try_from(code).unwrap(), mode);
+            assert_eq!(i32::try_from(mode).unwrap(), code);
+        }
+    }
+
+    #[test]
+    fn unknown_mode_codes() {
+        for &code in &[5, 7, 11, 13, 100, -1] {
+            assert!(matches!(
+                Mode::try_from(code),
+                Err(MrcError::FormatError(MrcFormatError::InvalidMode(c))) if c == code
+            ));
+        }
+        for &mode in &[Mode::IMOD, Mode::EPU, Mode::IVE] {
+            assert!(matches!(
+                i32::try_from(mode),
+                Err(MrcError::UnsupportedError(MrcUnsupportedError::UnsupportedMode(m))) if m == mode
+            ));
+        }
+    }
 }
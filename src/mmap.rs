@@ -0,0 +1,494 @@
+//! Memory mapped access to MRC files
+//!
+//! Mapping the file avoids copying the data block through `Read`, which dominates the time spent on
+//! multi-gigabyte maps. The data is handed out as typed slices straight from the mapping when the
+//! byte order of the file matches the host and the data block is suitably aligned. Otherwise the
+//! data is decoded from the mapping by the copying `Decoder`.
+//!
+//! Requires the `mmap` feature.
+
+use crate::bytecast;
+use crate::decoder::header::{Header, HEADER_SIZE};
+use crate::decoder::{ByteOrder, Decoder, DecodingResult};
+use crate::encoder::statistics::StatisticsAccumulator;
+use crate::encoder::stream::SmartWriter;
+use crate::encoder::{accumulate, EncodingBuffer};
+use crate::{Mode, MrcFormatError, MrcResult, MrcUnsupportedError};
+use memmap2::{Mmap, MmapMut};
+use std::fs::{File, OpenOptions};
+use std::io::Cursor;
+use std::ops::Range;
+use std::path::Path;
+
+/// Data block borrowed from a mapped file
+///
+/// The variants correspond to the ones of `DecodingResult`. Complex voxels are interleaved as
+/// real, imaginary and half-precision voxels are given as their bit patterns.
+#[derive(Debug, Clone, Copy)]
+pub enum MappedData<'a> {
+    I8(&'a [i8]),
+    U8(&'a [u8]),
+    I16(&'a [i16]),
+    U16(&'a [u16]),
+    F16(&'a [u16]),
+    F32(&'a [f32]),
+    ComplexI16(&'a [i16]),
+    ComplexF32(&'a [f32]),
+}
+
+/// Data block mutably borrowed from a writable mapping, see `MappedData`
+#[derive(Debug)]
+pub enum MappedDataMut<'a> {
+    I8(&'a mut [i8]),
+    U8(&'a mut [u8]),
+    I16(&'a mut [i16]),
+    U16(&'a mut [u16]),
+    F16(&'a mut [u16]),
+    F32(&'a mut [f32]),
+    ComplexI16(&'a mut [i16]),
+    ComplexF32(&'a mut [f32]),
+}
+
+/// Data block of a mapped file, either borrowed from the mapping or decoded from it
+#[derive(Debug)]
+pub enum MappedVolume<'a> {
+    Mapped(MappedData<'a>),
+    Decoded(DecodingResult),
+}
+
+/// Validated header and location of the data block of a mapped file
+#[derive(Debug)]
+struct Layout {
+    header: Header,
+    byte_order: ByteOrder,
+    mode: Mode,
+    /// Location of the data block in the mapping
+    data: Range<usize>,
+}
+
+impl Layout {
+    fn read(bytes: &[u8]) -> MrcResult<Layout> {
+        let decoder = Decoder::new(Cursor::new(bytes))?;
+        let byte_order = decoder.byte_order();
+        let header = decoder.header()?.clone();
+        let mode = header.data_mode()?;
+
+        let start = HEADER_SIZE + header.extended_header_size() as u64;
+        let end = start + header.data_size()?;
+        if end > bytes.len() as u64 {
+            return Err(MrcFormatError::Format(format!(
+                "data block ends at byte {}, the file has {} bytes",
+                end,
+                bytes.len()
+            ))
+            .into());
+        }
+
+        Ok(Layout {
+            header,
+            byte_order,
+            mode,
+            data: start as usize..end as usize,
+        })
+    }
+
+    /// Whether the data block can be used in place, as far as the byte order and mode go
+    fn is_native(&self) -> bool {
+        self.mode != Mode::Mode101
+            && (self.byte_order == ByteOrder::native()
+                || self.mode.bytes_per_voxel().is_some_and(|size| size == 1))
+    }
+
+    fn data<'a>(&self, bytes: &'a [u8]) -> Option<MappedData<'a>> {
+        if !self.is_native() {
+            return None;
+        }
+        match self.mode {
+            Mode::Mode0 if self.header.signed_bytes() => {
+                bytecast::ne_bytes_as_i8(bytes).map(MappedData::I8)
+            }
+            Mode::Mode0 | Mode::Mode16 => Some(MappedData::U8(bytes)),
+            Mode::Mode1 => bytecast::ne_bytes_as_i16(bytes).map(MappedData::I16),
+            Mode::Mode2 => bytecast::ne_bytes_as_f32(bytes).map(MappedData::F32),
+            Mode::Mode3 => bytecast::ne_bytes_as_i16(bytes).map(MappedData::ComplexI16),
+            Mode::Mode4 => bytecast::ne_bytes_as_f32(bytes).map(MappedData::ComplexF32),
+            Mode::Mode6 => bytecast::ne_bytes_as_u16(bytes).map(MappedData::U16),
+            Mode::Mode12 => bytecast::ne_bytes_as_u16(bytes).map(MappedData::F16),
+            _ => None,
+        }
+    }
+
+    fn data_mut<'a>(&self, bytes: &'a mut [u8]) -> Option<MappedDataMut<'a>> {
+        if !self.is_native() {
+            return None;
+        }
+        match self.mode {
+            Mode::Mode0 if self.header.signed_bytes() => {
+                bytecast::ne_mut_bytes_as_i8(bytes).map(MappedDataMut::I8)
+            }
+            Mode::Mode0 | Mode::Mode16 => Some(MappedDataMut::U8(bytes)),
+            Mode::Mode1 => bytecast::ne_mut_bytes_as_i16(bytes).map(MappedDataMut::I16),
+            Mode::Mode2 => bytecast::ne_mut_bytes_as_f32(bytes).map(MappedDataMut::F32),
+            Mode::Mode3 => bytecast::ne_mut_bytes_as_i16(bytes).map(MappedDataMut::ComplexI16),
+            Mode::Mode4 => bytecast::ne_mut_bytes_as_f32(bytes).map(MappedDataMut::ComplexF32),
+            Mode::Mode6 => bytecast::ne_mut_bytes_as_u16(bytes).map(MappedDataMut::U16),
+            Mode::Mode12 => bytecast::ne_mut_bytes_as_u16(bytes).map(MappedDataMut::F16),
+            _ => None,
+        }
+    }
+}
+
+/// A memory mapped MRC file
+#[derive(Debug)]
+pub struct MappedMrc {
+    map: Mmap,
+    layout: Layout,
+}
+
+impl MappedMrc {
+    /// Maps the file at `path` and validates its header
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated while it is mapped, see `memmap2::Mmap::map`.
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> MrcResult<MappedMrc> {
+        let file = File::open(path)?;
+        MappedMrc::from_mmap(Mmap::map(&file)?)
+    }
+
+    /// Validates the header of an already mapped file
+    pub fn from_mmap(map: Mmap) -> MrcResult<MappedMrc> {
+        let layout = Layout::read(&map)?;
+        Ok(MappedMrc { map, layout })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.layout.header
+    }
+
+    /// Byte order of the file
+    pub fn byte_order(&self) -> ByteOrder {
+        self.layout.byte_order
+    }
+
+    /// Raw bytes of the data block
+    pub fn data_bytes(&self) -> &[u8] {
+        &self.map[self.layout.data.clone()]
+    }
+
+    /// Data block as a typed slice into the mapping
+    ///
+    /// `None` when the byte order of the file differs from the one of the host, when the data
+    /// block is not aligned for its type or when the voxels are packed (`Mode101`).
+    pub fn data(&self) -> Option<MappedData<'_>> {
+        self.layout.data(self.data_bytes())
+    }
+
+    /// A copying decoder reading from the mapping
+    ///
+    /// This gives access to sections and regions of files that can not be borrowed by `data`.
+    pub fn decoder(&self) -> MrcResult<Decoder<Cursor<&[u8]>>> {
+        Decoder::new(Cursor::new(&self.map[..]))
+    }
+
+    /// Data block borrowed from the mapping if possible, decoded from it otherwise
+    ///
+    /// Half-precision data stays half-precision in both cases.
+    pub fn volume(&self) -> MrcResult<MappedVolume<'_>> {
+        match self.data() {
+            Some(data) => Ok(MappedVolume::Mapped(data)),
+            None => Ok(MappedVolume::Decoded(
+                self.decoder()?.with_native_half(true).read_volume()?,
+            )),
+        }
+    }
+}
+
+/// A writable memory mapped MRC file, for editing the data block in place
+///
+/// The density statistics in the header are recomputed and rewritten by `close`. Changes to the
+/// data reach the file even without `close`, but the statistics then describe the old data.
+#[derive(Debug)]
+pub struct MappedMrcMut {
+    map: MmapMut,
+    layout: Layout,
+}
+
+impl MappedMrcMut {
+    /// Maps the file at `path` for reading and writing and validates its header
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated by others while it is mapped, see
+    /// `memmap2::MmapMut::map_mut`.
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> MrcResult<MappedMrcMut> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        MappedMrcMut::from_mmap(MmapMut::map_mut(&file)?)
+    }
+
+    /// Validates the header of an already mapped file
+    pub fn from_mmap(map: MmapMut) -> MrcResult<MappedMrcMut> {
+        let layout = Layout::read(&map)?;
+        Ok(MappedMrcMut { map, layout })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.layout.header
+    }
+
+    /// Byte order of the file
+    pub fn byte_order(&self) -> ByteOrder {
+        self.layout.byte_order
+    }
+
+    /// Raw bytes of the data block
+    pub fn data_bytes(&self) -> &[u8] {
+        &self.map[self.layout.data.clone()]
+    }
+
+    /// Raw bytes of the data block, for editing data that can not be borrowed by `data_mut`
+    pub fn data_bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.map[self.layout.data.clone()]
+    }
+
+    /// Data block as a typed slice into the mapping, see `MappedMrc::data`
+    pub fn data(&self) -> Option<MappedData<'_>> {
+        self.layout.data(self.data_bytes())
+    }
+
+    /// Data block as a mutable typed slice into the mapping
+    ///
+    /// `None` in the same cases as `data`.
+    pub fn data_mut(&mut self) -> Option<MappedDataMut<'_>> {
+        let data = self.layout.data.clone();
+        self.layout.data_mut(&mut self.map[data])
+    }
+
+    /// A copying decoder reading from the mapping
+    pub fn decoder(&self) -> MrcResult<Decoder<Cursor<&[u8]>>> {
+        Decoder::new(Cursor::new(&self.map[..]))
+    }
+
+    /// Recomputes the density statistics, writes them to the header and flushes the mapping
+    pub fn close(mut self) -> MrcResult<()> {
+        let mut statistics = StatisticsAccumulator::new();
+        let mode = self.layout.mode;
+        match self.data() {
+            Some(data) => accumulate(&mut statistics, mode, &mapped_buffer(data)),
+            // Decoded a section at a time, so that the volume is never copied as a whole
+            None => {
+                let mut decoder = self.decoder()?.with_native_half(true);
+                for section in decoder.sections() {
+                    accumulate(&mut statistics, mode, &decoded_buffer(&section?)?);
+                }
+            }
+        }
+        self.layout
+            .header
+            .set_density_statistics(statistics.statistics());
+
+        let header = &mut self.map[..HEADER_SIZE as usize];
+        let mut writer = SmartWriter::wrap(Cursor::new(header), self.layout.byte_order);
+        self.layout.header.write_density_statistics(&mut writer)?;
+        self.map.flush()?;
+        Ok(())
+    }
+}
+
+fn mapped_buffer(data: MappedData<'_>) -> EncodingBuffer<'_> {
+    match data {
+        MappedData::I8(buf) => EncodingBuffer::I8(buf),
+        MappedData::U8(buf) => EncodingBuffer::U8(buf),
+        MappedData::I16(buf) => EncodingBuffer::I16(buf),
+        MappedData::U16(buf) => EncodingBuffer::U16(buf),
+        MappedData::F16(buf) => EncodingBuffer::F16(buf),
+        MappedData::F32(buf) => EncodingBuffer::F32(buf),
+        MappedData::ComplexI16(buf) => EncodingBuffer::ComplexI16(buf),
+        MappedData::ComplexF32(buf) => EncodingBuffer::ComplexF32(buf),
+    }
+}
+
+fn decoded_buffer(result: &DecodingResult) -> MrcResult<EncodingBuffer<'_>> {
+    Ok(match *result {
+        DecodingResult::I8(ref buf) => EncodingBuffer::I8(buf),
+        DecodingResult::U8(ref buf) => EncodingBuffer::U8(buf),
+        DecodingResult::I16(ref buf) => EncodingBuffer::I16(buf),
+        DecodingResult::U16(ref buf) => EncodingBuffer::U16(buf),
+        DecodingResult::F16(ref buf) => EncodingBuffer::F16(buf),
+        DecodingResult::F32(ref buf) => EncodingBuffer::F32(buf),
+        DecodingResult::ComplexI16(ref buf) => EncodingBuffer::ComplexI16(buf),
+        DecodingResult::ComplexF32(ref buf) => EncodingBuffer::ComplexF32(buf),
+        _ => return Err(MrcUnsupportedError::UnsupportedDataType.into()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::header::{IVE_ID, IVE_ID_OFFSET};
+    use crate::encoder::Encoder;
+    use std::path::PathBuf;
+
+    /// Writes `bytes` to a file named `name` in the temporary directory
+    fn temporary_file(name: &str, bytes: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("mrc-{}-{}", std::process::id(), name));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    fn encode(mode: Mode, dims: [usize; 3], data: EncodingBuffer) -> Vec<u8> {
+        let mut encoder =
+            Encoder::new(Cursor::new(Vec::new())).with_byte_order(ByteOrder::native());
+        encoder.write_volume(mode, dims, data).unwrap();
+        encoder.into_inner().into_inner()
+    }
+
+    #[test]
+    fn closing_keeps_the_priism_origin() {
+        let mut bytes = encode(
+            Mode::Mode2,
+            [2, 2, 1],
+            EncodingBuffer::F32(&[1.0, 2.0, 3.0, 4.0]),
+        );
+        let offset = IVE_ID_OFFSET as usize;
+        bytes[offset..offset + 2].copy_from_slice(&IVE_ID.to_ne_bytes());
+        for (i, value) in [3.0f32, 1.0, 2.0].iter().enumerate() {
+            bytes[208 + 4 * i..212 + 4 * i].copy_from_slice(&value.to_ne_bytes());
+        }
+        let path = temporary_file("priism", &bytes);
+
+        let mut mapped = unsafe { MappedMrcMut::open(&path) }.unwrap();
+        assert_eq!(mapped.header().origin(), [1.0, 2.0, 3.0]);
+        assert_eq!(mapped.header().density_statistics().rms(), None);
+        match mapped.data_mut() {
+            Some(MappedDataMut::F32(data)) => data[3] = 8.0,
+            data => panic!("unexpected data {:?}", data),
+        }
+        mapped.close().unwrap();
+
+        let closed = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(closed[208..220], bytes[208..220]);
+        let decoder = Decoder::new(Cursor::new(&closed[..])).unwrap();
+        let header = decoder.header().unwrap();
+        assert_eq!(header.origin(), [1.0, 2.0, 3.0]);
+        assert_eq!(header.density_statistics().max, 8.0);
+    }
+    fn encode_in(byte_order: ByteOrder, data: &[i16]) -> Vec<u8> {
+        let mut encoder = Encoder::new(Cursor::new(Vec::new())).with_byte_order(byte_order);
+        encoder
+            .write_volume(Mode::Mode1, [3, 2, 1], EncodingBuffer::I16(data))
+            .unwrap();
+        encoder.into_inner().into_inner()
+    }
+
+    fn foreign() -> ByteOrder {
+        match ByteOrder::native() {
+            ByteOrder::LittleEndian => ByteOrder::BigEndian,
+            ByteOrder::BigEndian => ByteOrder::LittleEndian,
+        }
+    }
+
+    fn map(bytes: &[u8]) -> MrcResult<MappedMrc> {
+        let mut map = MmapMut::map_anon(bytes.len()).unwrap();
+        map.copy_from_slice(bytes);
+        MappedMrc::from_mmap(map.make_read_only().unwrap())
+    }
+
+    const DATA: [i16; 6] = [-3, 1, 4, -1, 5, 9];
+
+    #[test]
+    fn typed_slices_of_native_files() {
+        let mapped = map(&encode_in(ByteOrder::native(), &DATA)).unwrap();
+        assert_eq!(mapped.byte_order(), ByteOrder::native());
+        assert_eq!(mapped.data_bytes().len(), 12);
+        match mapped.data() {
+            Some(MappedData::I16(data)) => assert_eq!(data, DATA),
+            data => panic!("unexpected data {:?}", data),
+        }
+        assert!(matches!(
+            mapped.volume().unwrap(),
+            MappedVolume::Mapped(MappedData::I16(_))
+        ));
+    }
+
+    #[test]
+    fn decoded_fallback() {
+        // Foreign byte order
+        let mapped = map(&encode_in(foreign(), &DATA)).unwrap();
+        assert_eq!(mapped.byte_order(), foreign());
+        assert!(mapped.data().is_none());
+        match mapped.volume().unwrap() {
+            MappedVolume::Decoded(DecodingResult::I16(data)) => assert_eq!(data, DATA),
+            volume => panic!("unexpected volume {:?}", volume),
+        }
+
+        // Data block misaligned by an extended header of odd size
+        let mut encoder = Encoder::new(Cursor::new(Vec::new()))
+            .with_byte_order(ByteOrder::native())
+            .with_extended_header(None, vec![0; 3]);
+        encoder
+            .write_volume(Mode::Mode1, [3, 2, 1], EncodingBuffer::I16(&DATA))
+            .unwrap();
+        let mapped = map(&encoder.into_inner().into_inner()).unwrap();
+        assert!(mapped.data().is_none());
+        match mapped.volume().unwrap() {
+            MappedVolume::Decoded(DecodingResult::I16(data)) => assert_eq!(data, DATA),
+            volume => panic!("unexpected volume {:?}", volume),
+        }
+
+        // Bytes are independent of the byte order
+        for &byte_order in &[ByteOrder::native(), foreign()] {
+            let mut encoder = Encoder::new(Cursor::new(Vec::new())).with_byte_order(byte_order);
+            encoder
+                .write_volume(Mode::Mode0, [4, 1, 1], EncodingBuffer::I8(&[-1, 0, 1, 2]))
+                .unwrap();
+            match map(&encoder.into_inner().into_inner()).unwrap().data() {
+                Some(MappedData::I8(data)) => assert_eq!(data, [-1, 0, 1, 2]),
+                data => panic!("unexpected data {:?}", data),
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_data_block() {
+        let bytes = encode_in(ByteOrder::native(), &DATA);
+        assert!(map(&bytes[..bytes.len() - 1]).is_err());
+    }
+    /// Edits the last voxel of a mapped file of `DATA` in `byte_order` to 21 and closes it
+    fn edit_and_close(name: &str, byte_order: ByteOrder) -> Header {
+        let path = temporary_file(name, &encode_in(byte_order, &DATA));
+        let mut mapped = unsafe { MappedMrcMut::open(&path) }.unwrap();
+        assert_eq!(mapped.header().density_statistics().max, 9.0);
+        match mapped.data_mut() {
+            Some(MappedDataMut::I16(data)) => data[5] = 21,
+            None => {
+                let bytes = mapped.data_bytes_mut();
+                bytes[10..].copy_from_slice(&match byte_order {
+                    ByteOrder::LittleEndian => 21i16.to_le_bytes(),
+                    ByteOrder::BigEndian => 21i16.to_be_bytes(),
+                });
+            }
+            data => panic!("unexpected data {:?}", data),
+        }
+        mapped.close().unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let decoder = Decoder::new(Cursor::new(bytes)).unwrap();
+        decoder.header().unwrap().clone()
+    }
+
+    #[test]
+    fn closing_refreshes_the_statistics() {
+        let data = [-3.0, 1.0, 4.0, -1.0, 5.0, 21.0];
+        let mean = data.iter().sum::<f64>() / 6.0;
+        let rms = (data.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / 6.0).sqrt();
+        for &(name, byte_order) in &[("native", ByteOrder::native()), ("foreign", foreign())] {
+            let statistics = edit_and_close(name, byte_order).density_statistics();
+            assert_eq!(statistics.range(), Some((-3.0, 21.0)));
+            assert_eq!(statistics.mean(), Some(4.5));
+            assert!((statistics.rms().unwrap() as f64 - rms).abs() < 1e-4);
+        }
+    }
+}